@@ -0,0 +1,919 @@
+use std::{collections::HashMap, io::Read};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::font::{GlyphSource, Tile};
+
+const SHEET_COLUMNS: u32 = 16;
+const SHEET_ROWS: u32 = 16;
+
+const PSF1_MAGIC: &[u8] = &[0x36, 0x04];
+const PSF2_MAGIC: &[u8] = &[0x72, 0xb5, 0x4a, 0x86];
+const PCF_MAGIC: &[u8] = b"\x01fcp";
+const BDF_MAGIC: &[u8] = b"STARTFONT";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+struct BitmapGlyph {
+    // position of the bitmap from the top left corner of the cell
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    coverage: Vec<u8>,
+}
+
+impl BitmapGlyph {
+    fn coverage_at(&self, x: i32, y: i32) -> u8 {
+        let (x, y) = (x - self.x, y - self.y);
+
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return 0;
+        }
+
+        self.coverage[(y as u32 * self.width + x as u32) as usize]
+    }
+}
+
+/// Bitmap font: a PSF, BDF or PCF console font, or the bundled glyph sheet.
+pub struct BitmapFont {
+    cell_size: (u32, u32),
+    glyphs: Vec<BitmapGlyph>,
+    // glyph of each grapheme cluster
+    clusters: HashMap<String, usize>,
+}
+
+impl BitmapFont {
+    /// Returns whether `data` looks like a bitmap font this module can load.
+    pub fn detect(data: &[u8]) -> bool {
+        [PSF1_MAGIC, PSF2_MAGIC, PCF_MAGIC, BDF_MAGIC, GZIP_MAGIC]
+            .iter()
+            .any(|magic| data.starts_with(magic))
+    }
+
+    pub fn load(data: &[u8]) -> Result<Self> {
+        if data.starts_with(GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(data)
+                .read_to_end(&mut decompressed)
+                .context("decompressing font")?;
+            return Self::load(&decompressed);
+        }
+
+        if data.starts_with(PSF1_MAGIC) {
+            Self::from_psf1(data)
+        } else if data.starts_with(PSF2_MAGIC) {
+            Self::from_psf2(data)
+        } else if data.starts_with(PCF_MAGIC) {
+            Self::from_pcf(data)
+        } else if data.starts_with(BDF_MAGIC) {
+            // properties like COPYRIGHT are often Latin-1
+            Self::from_bdf(&String::from_utf8_lossy(data))
+        } else {
+            bail!("not a PSF, BDF or PCF font")
+        }
+    }
+
+    /// The glyph sheet embedded in the binary, 16×16 glyphs for the first 256 code points.
+    pub fn bundled() -> Result<Self> {
+        let image = image::load_from_memory(include_bytes!("../assets/font.png"))?.to_luma8();
+        let (width, height) = (image.width() / SHEET_COLUMNS, image.height() / SHEET_ROWS);
+
+        let glyphs = (0..SHEET_COLUMNS * SHEET_ROWS)
+            .map(|code| {
+                let origin = (
+                    (code % SHEET_COLUMNS) * width,
+                    (code / SHEET_COLUMNS) * height,
+                );
+                let coverage = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| image.get_pixel(origin.0 + x, origin.1 + y)[0])
+                    .collect();
+
+                BitmapGlyph {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                    coverage,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            cell_size: (width, height),
+            glyphs,
+            clusters: Self::identity_map(SHEET_COLUMNS * SHEET_ROWS),
+        })
+    }
+
+    fn from_psf1(data: &[u8]) -> Result<Self> {
+        let mode = *data.get(2).ok_or(anyhow!("truncated PSF header"))?;
+        let height = *data.get(3).ok_or(anyhow!("truncated PSF header"))? as u32;
+        let count = if mode & 0x01 != 0 { 512 } else { 256 };
+        if height == 0 {
+            bail!("PSF glyphs of height 0");
+        }
+
+        let glyph_data = data
+            .get(4..4 + count * height as usize)
+            .ok_or(anyhow!("truncated PSF glyphs"))?;
+
+        let glyphs = glyph_data
+            .chunks(height as usize)
+            .map(|rows| Self::glyph_from_rows(rows, 8, height, 1))
+            .collect();
+
+        // the Unicode table holds little endian code units, 0xfffe starts a sequence and 0xffff
+        // ends the entries of a glyph
+        let clusters = if mode & 0x06 != 0 {
+            let units: Vec<u16> = data[4 + glyph_data.len()..]
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+
+            Self::unicode_table(units.split(|&unit| unit == 0xffff).take(count), |entry| {
+                entry
+                    .split(|&unit| unit == 0xfffe)
+                    .enumerate()
+                    .flat_map(|(index, units)| {
+                        let chars =
+                            char::decode_utf16(units.iter().copied()).filter_map(Result::ok);
+
+                        // the code points before the first separator are each a glyph alias
+                        if index == 0 {
+                            chars.map(String::from).collect::<Vec<_>>()
+                        } else {
+                            vec![chars.collect()]
+                        }
+                    })
+                    .collect()
+            })
+        } else {
+            Self::identity_map(count as u32)
+        };
+
+        Ok(Self {
+            cell_size: (8, height),
+            glyphs,
+            clusters,
+        })
+    }
+
+    fn from_psf2(data: &[u8]) -> Result<Self> {
+        let field = |index: usize| -> Result<u32> {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .ok_or(anyhow!("truncated PSF2 header"))?;
+            Ok(u32::from_le_bytes(bytes.try_into()?))
+        };
+
+        let (header_size, flags, count) = (field(2)? as usize, field(3)?, field(4)? as usize);
+        let (glyph_size, height, width) = (field(5)? as usize, field(6)?, field(7)?);
+        if width == 0 || height == 0 {
+            bail!("PSF2 glyphs of {width}x{height} pixels");
+        }
+
+        // also keeps the glyphs from being larger than the file holds
+        let rows_size = (width.div_ceil(8) as usize).checked_mul(height as usize);
+        if rows_size.is_none_or(|rows_size| glyph_size < rows_size) {
+            bail!("PSF2 glyphs of {glyph_size} bytes for {width}x{height} pixels");
+        }
+
+        let glyph_data = count
+            .checked_mul(glyph_size)
+            .and_then(|size| header_size.checked_add(size))
+            .and_then(|end| data.get(header_size..end))
+            .ok_or(anyhow!("truncated PSF2 glyphs"))?;
+
+        let glyphs = glyph_data
+            .chunks(glyph_size)
+            .map(|rows| Self::glyph_from_rows(rows, width, height, width.div_ceil(8)))
+            .collect();
+
+        // the Unicode table holds UTF-8 strings, 0xfe starts a sequence and 0xff ends the entries
+        // of a glyph
+        let clusters = if flags & 0x01 != 0 {
+            let table = &data[header_size + glyph_data.len()..];
+
+            Self::unicode_table(table.split(|&byte| byte == 0xff).take(count), |entry| {
+                entry
+                    .split(|&byte| byte == 0xfe)
+                    .enumerate()
+                    .flat_map(|(index, bytes)| {
+                        let string = String::from_utf8_lossy(bytes);
+
+                        if index == 0 {
+                            string.chars().map(String::from).collect::<Vec<_>>()
+                        } else {
+                            vec![string.into_owned()]
+                        }
+                    })
+                    .collect()
+            })
+        } else {
+            Self::identity_map(count as u32)
+        };
+
+        Ok(Self {
+            cell_size: (width, height),
+            glyphs,
+            clusters,
+        })
+    }
+
+    fn from_bdf(text: &str) -> Result<Self> {
+        let mut cell_size = (0, 0);
+        let mut ascent = None;
+        let mut descent = None;
+        let mut glyphs = Vec::new();
+        let mut clusters = HashMap::new();
+
+        let mut encoding = None;
+        let mut bounding_box = [0i32; 4];
+        let mut bitmap: Option<Vec<&str>> = None;
+
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let numbers: Vec<i32> = words.filter_map(|word| word.parse().ok()).collect();
+
+            if let Some(rows) = &mut bitmap {
+                if keyword != "ENDCHAR" {
+                    rows.push(keyword);
+                    continue;
+                }
+
+                let [width, height, x, y] = bounding_box;
+                let row_bytes = (width as u32).div_ceil(8);
+
+                // also keeps the glyph from being larger than the file holds
+                if rows.len() < height as usize
+                    || rows.iter().any(|row| row.len() < row_bytes as usize * 2)
+                {
+                    bail!("BDF glyph bitmap smaller than its {width}x{height} bounding box");
+                }
+                let bytes: Vec<u8> = rows
+                    .iter()
+                    .flat_map(|row| {
+                        (0..row_bytes as usize).map(|byte| {
+                            row.get(byte * 2..byte * 2 + 2)
+                                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                                .unwrap_or(0)
+                        })
+                    })
+                    .collect();
+
+                if let Some(code) = encoding.and_then(char::from_u32) {
+                    // bitmaps are placed from the baseline, with y going up
+                    let mut glyph =
+                        Self::glyph_from_rows(&bytes, width as u32, height as u32, row_bytes);
+                    glyph.x = x;
+                    glyph.y = ascent.unwrap_or(cell_size.1 as i32) - y - height;
+
+                    clusters.insert(String::from(code), glyphs.len());
+                    glyphs.push(glyph);
+                }
+
+                bitmap = None;
+                continue;
+            }
+
+            match (keyword, numbers.as_slice()) {
+                ("FONTBOUNDINGBOX", &[width, height, _, y, ..]) => {
+                    cell_size = (width as u32, height as u32);
+                    ascent = ascent.or(Some(height + y));
+                    descent = descent.or(Some(-y));
+                }
+                ("FONT_ASCENT", &[value, ..]) => ascent = Some(value),
+                ("FONT_DESCENT", &[value, ..]) => descent = Some(value),
+                ("STARTCHAR", _) => encoding = None,
+                ("ENCODING", &[code, ..]) => encoding = u32::try_from(code).ok(),
+                ("BBX", &[width, height, ..]) if width < 0 || height < 0 => {
+                    bail!("BDF bounding box of {width}x{height} pixels")
+                }
+                ("BBX", &[width, height, x, y, ..]) => bounding_box = [width, height, x, y],
+                ("BITMAP", _) => bitmap = Some(Vec::new()),
+                _ => {}
+            }
+        }
+
+        if let (Some(ascent), Some(descent)) = (ascent, descent) {
+            cell_size.1 = (ascent + descent).max(1) as u32;
+        }
+
+        if glyphs.is_empty() {
+            bail!("no glyphs in BDF font");
+        }
+
+        Ok(Self {
+            cell_size,
+            glyphs,
+            clusters,
+        })
+    }
+
+    fn from_pcf(data: &[u8]) -> Result<Self> {
+        let pcf = Pcf::new(data)?;
+        let (ascent, descent) = pcf.accelerators()?;
+        let metrics = pcf.metrics()?;
+
+        // the device is created with the default limits, so no tile could hold a larger glyph
+        let max_size = wgpu::Limits::default().max_texture_dimension_2d;
+        if let Some(metrics) = metrics
+            .iter()
+            .find(|metrics| metrics.width() > max_size || metrics.height() > max_size)
+        {
+            bail!(
+                "PCF glyph of {}x{} pixels, larger than {max_size}",
+                metrics.width(),
+                metrics.height()
+            );
+        }
+        let bitmaps = pcf.bitmaps(&metrics)?;
+
+        let glyphs: Vec<BitmapGlyph> = metrics
+            .iter()
+            .zip(bitmaps)
+            .map(|(metrics, coverage)| BitmapGlyph {
+                x: metrics.left_bearing,
+                y: ascent - metrics.ascent,
+                width: metrics.width(),
+                height: metrics.height(),
+                coverage,
+            })
+            .collect();
+
+        // assumes an ISO 10646 or ISO 8859-1 font, where encodings are code points
+        let clusters: HashMap<String, usize> = pcf
+            .encodings()?
+            .into_iter()
+            .filter(|&(_, index)| index < glyphs.len())
+            .filter_map(|(code, index)| Some((String::from(char::from_u32(code)?), index)))
+            .collect();
+
+        // the advance of 'M' like for TrueType fonts, or the most common one without it, as wide
+        // glyphs advance two cells
+        let width = match clusters.get("M") {
+            Some(&index) => metrics[index].advance,
+            None => {
+                let mut counts: HashMap<i32, usize> = HashMap::new();
+                for metrics in &metrics[..glyphs.len()] {
+                    *counts.entry(metrics.advance).or_default() += 1;
+                }
+                counts
+                    .into_iter()
+                    .max_by_key(|&(advance, count)| (count, advance))
+                    .map_or(1, |(advance, _)| advance)
+            }
+        };
+
+        Ok(Self {
+            cell_size: (width.max(1) as u32, (ascent + descent).max(1) as u32),
+            glyphs,
+            clusters,
+        })
+    }
+
+    // Expands 1 bit per pixel rows, most significant bit first, into coverage
+    fn glyph_from_rows(rows: &[u8], width: u32, height: u32, row_bytes: u32) -> BitmapGlyph {
+        let coverage = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let byte = rows
+                    .get((y * row_bytes + x / 8) as usize)
+                    .copied()
+                    .unwrap_or(0);
+                if byte & (0x80 >> (x % 8)) != 0 {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        BitmapGlyph {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            coverage,
+        }
+    }
+
+    fn identity_map(count: u32) -> HashMap<String, usize> {
+        (0..count)
+            .filter_map(|code| Some((String::from(char::from_u32(code)?), code as usize)))
+            .collect()
+    }
+
+    fn unicode_table<'a, E: 'a>(
+        entries: impl Iterator<Item = E>,
+        clusters: impl Fn(E) -> Vec<String>,
+    ) -> HashMap<String, usize> {
+        entries
+            .enumerate()
+            .flat_map(|(index, entry)| {
+                clusters(entry)
+                    .into_iter()
+                    .map(move |cluster| (cluster, index))
+            })
+            .filter(|(cluster, _)| !cluster.is_empty())
+            .collect()
+    }
+}
+
+impl GlyphSource for BitmapFont {
+    fn cell_size(&self) -> (u32, u32) {
+        self.cell_size
+    }
+
+    fn draw(&mut self, cluster: &str, tile: &mut Tile) -> bool {
+        let Some(glyph) = self.clusters.get(cluster).map(|&index| &self.glyphs[index]) else {
+            return false;
+        };
+
+        // scaled to the cell of the tile with nearest neighbour sampling, over both of its cells
+        // for wide glyphs
+        let (width, height) = (tile.glyph_size.0 * 2, tile.glyph_size.1);
+        let coverage: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                glyph.coverage_at(
+                    (x * self.cell_size.0 / tile.glyph_size.0) as i32,
+                    (y * self.cell_size.1 / tile.glyph_size.1) as i32,
+                )
+            })
+            .collect();
+
+        tile.blit(0, 0, width, height, &coverage);
+        true
+    }
+}
+
+struct PcfMetrics {
+    left_bearing: i32,
+    right_bearing: i32,
+    advance: i32,
+    ascent: i32,
+    descent: i32,
+}
+
+impl PcfMetrics {
+    fn width(&self) -> u32 {
+        (self.right_bearing - self.left_bearing).max(0) as u32
+    }
+
+    fn height(&self) -> u32 {
+        (self.ascent + self.descent).max(0) as u32
+    }
+}
+
+// X11 Portable Compiled Format, a table of contents followed by tables each starting with
+// their own format
+struct Pcf<'a> {
+    data: &'a [u8],
+    tables: Vec<(u32, usize)>,
+}
+
+impl<'a> Pcf<'a> {
+    const ACCELERATORS: u32 = 1 << 1;
+    const METRICS: u32 = 1 << 2;
+    const BITMAPS: u32 = 1 << 3;
+    const BDF_ENCODINGS: u32 = 1 << 5;
+    const BDF_ACCELERATORS: u32 = 1 << 8;
+
+    const BYTE_MSB_FIRST: u32 = 1 << 2;
+    const BIT_MSB_FIRST: u32 = 1 << 3;
+    const COMPRESSED_METRICS: u32 = 0x100;
+
+    fn new(data: &'a [u8]) -> Result<Self> {
+        let mut reader = PcfReader::new(data, 4, 0);
+        let count = reader.u32()?;
+        let tables = (0..count)
+            .map(|_| {
+                let kind = reader.u32()?;
+                let (_format, _size, offset) = (reader.u32()?, reader.u32()?, reader.u32()?);
+                Ok((kind, offset as usize))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { data, tables })
+    }
+
+    fn table(&self, kind: u32) -> Result<PcfReader<'a>> {
+        let &(_, offset) = self
+            .tables
+            .iter()
+            .find(|(table, _)| *table == kind)
+            .ok_or(anyhow!("missing PCF table {kind:#x}"))?;
+
+        let format = PcfReader::new(self.data, offset, 0).u32()?;
+        Ok(PcfReader::new(self.data, offset + 4, format))
+    }
+
+    fn accelerators(&self) -> Result<(i32, i32)> {
+        let mut reader = self
+            .table(Self::BDF_ACCELERATORS)
+            .or_else(|_| self.table(Self::ACCELERATORS))?;
+
+        reader.skip(8);
+        Ok((reader.u32()? as i32, reader.u32()? as i32))
+    }
+
+    fn metrics(&self) -> Result<Vec<PcfMetrics>> {
+        let mut reader = self.table(Self::METRICS)?;
+
+        if reader.format & Self::COMPRESSED_METRICS != 0 {
+            let count = reader.u16()?;
+            (0..count)
+                .map(|_| {
+                    let mut byte = || Ok::<_, anyhow::Error>(reader.u8()? as i32 - 0x80);
+                    Ok(PcfMetrics {
+                        left_bearing: byte()?,
+                        right_bearing: byte()?,
+                        advance: byte()?,
+                        ascent: byte()?,
+                        descent: byte()?,
+                    })
+                })
+                .collect()
+        } else {
+            let count = reader.u32()?;
+            (0..count)
+                .map(|_| {
+                    let metrics = PcfMetrics {
+                        left_bearing: reader.u16()? as i16 as i32,
+                        right_bearing: reader.u16()? as i16 as i32,
+                        advance: reader.u16()? as i16 as i32,
+                        ascent: reader.u16()? as i16 as i32,
+                        descent: reader.u16()? as i16 as i32,
+                    };
+                    reader.skip(2);
+                    Ok(metrics)
+                })
+                .collect()
+        }
+    }
+
+    fn bitmaps(&self, metrics: &[PcfMetrics]) -> Result<Vec<Vec<u8>>> {
+        let mut reader = self.table(Self::BITMAPS)?;
+        let format = reader.format;
+        let count = reader.u32()? as usize;
+        let offsets = (0..count)
+            .map(|_| Ok(reader.u32()? as usize))
+            .collect::<Result<Vec<_>>>()?;
+        // size of the bitmaps with each of the row paddings
+        let sizes = (0..4)
+            .map(|_| Ok(reader.u32()? as usize))
+            .collect::<Result<Vec<_>>>()?;
+        let table = self
+            .data
+            .get(reader.position..)
+            .and_then(|data| data.get(..sizes[(format & 3) as usize]))
+            .ok_or(anyhow!("truncated PCF bitmaps"))?;
+
+        // rows are padded to 1, 2, 4 or 8 bytes, and stored in units of 1, 2 or 4 bytes
+        let row_padding = 1 << (format & 3);
+        let unit = 1 << ((format >> 4) & 3);
+        let swap_units =
+            (format & Self::BYTE_MSB_FIRST != 0) != (format & Self::BIT_MSB_FIRST != 0);
+
+        metrics
+            .iter()
+            .zip(offsets)
+            .map(|(metrics, offset)| {
+                let row_bytes = (metrics.width() as usize)
+                    .div_ceil(8)
+                    .next_multiple_of(row_padding);
+                let length = row_bytes * metrics.height() as usize;
+                let mut bytes = table
+                    .get(offset..)
+                    .and_then(|bitmap| bitmap.get(..length))
+                    .ok_or(anyhow!(
+                        "PCF bitmap of {length} bytes at {offset} past the end of its table"
+                    ))?
+                    .to_vec();
+
+                if swap_units && unit > 1 {
+                    bytes.chunks_exact_mut(unit).for_each(<[u8]>::reverse);
+                }
+                if format & Self::BIT_MSB_FIRST == 0 {
+                    bytes
+                        .iter_mut()
+                        .for_each(|byte| *byte = byte.reverse_bits());
+                }
+
+                Ok(BitmapFont::glyph_from_rows(
+                    &bytes,
+                    metrics.width(),
+                    metrics.height(),
+                    row_bytes as u32,
+                )
+                .coverage)
+            })
+            .collect()
+    }
+
+    // Code point and glyph index of each encoded character
+    fn encodings(&self) -> Result<Vec<(u32, usize)>> {
+        let mut reader = self.table(Self::BDF_ENCODINGS)?;
+        let (min_byte2, max_byte2) = (reader.u16()? as u32, reader.u16()? as u32);
+        let (min_byte1, max_byte1) = (reader.u16()? as u32, reader.u16()? as u32);
+        reader.skip(2);
+
+        let mut encodings = Vec::new();
+        for byte1 in min_byte1..=max_byte1 {
+            for byte2 in min_byte2..=max_byte2 {
+                let index = reader.u16()?;
+                if index != 0xffff {
+                    encodings.push((byte1 << 8 | byte2, index as usize));
+                }
+            }
+        }
+
+        Ok(encodings)
+    }
+}
+
+struct PcfReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    format: u32,
+}
+
+impl<'a> PcfReader<'a> {
+    fn new(data: &'a [u8], position: usize, format: u32) -> Self {
+        Self {
+            data,
+            position,
+            format,
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + N)
+            .ok_or(anyhow!("truncated PCF font"))?;
+        self.position += N;
+
+        let mut bytes: [u8; N] = bytes.try_into()?;
+        if self.format & Pcf::BYTE_MSB_FIRST == 0 {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes()?))
+    }
+
+    fn skip(&mut self, count: usize) {
+        self.position += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn psf2(count: u32, glyph_size: u32, (width, height): (u32, u32), glyphs: &[u8]) -> Vec<u8> {
+        let fields = [0, 32, 0, count, glyph_size, height, width];
+        let mut data = PSF2_MAGIC.to_vec();
+        data.extend(fields.iter().flat_map(|field| field.to_le_bytes()));
+        data.extend_from_slice(glyphs);
+        data
+    }
+
+    #[test]
+    fn psf1() {
+        let mut data = PSF1_MAGIC.to_vec();
+        data.extend([0, 2]);
+        data.extend((0..256).flat_map(|code| [code as u8, 0x80]));
+
+        let font = BitmapFont::load(&data).unwrap();
+        assert_eq!(font.cell_size, (8, 2));
+        assert_eq!(font.glyphs.len(), 256);
+        assert_eq!(font.clusters["A"], 0x41);
+        assert_eq!(font.glyphs[0x41].coverage_at(1, 0), 255);
+        assert_eq!(font.glyphs[0x41].coverage_at(0, 1), 255);
+    }
+
+    #[test]
+    fn psf1_truncated_or_empty() {
+        assert!(BitmapFont::load(PSF1_MAGIC).is_err());
+
+        let mut data = PSF1_MAGIC.to_vec();
+        data.extend([0, 8]);
+        data.extend([0; 100]);
+        assert!(BitmapFont::load(&data).is_err());
+
+        let mut data = PSF1_MAGIC.to_vec();
+        data.extend([0, 0]);
+        assert!(BitmapFont::load(&data).is_err());
+    }
+
+    #[test]
+    fn psf2_wide_glyphs() {
+        let data = psf2(2, 4, (10, 2), &[0xff, 0xc0, 0, 0, 0, 0, 0x80, 0x40]);
+
+        let font = BitmapFont::load(&data).unwrap();
+        assert_eq!(font.cell_size, (10, 2));
+        assert_eq!(font.glyphs[0].coverage_at(9, 0), 255);
+        assert_eq!(font.glyphs[0].coverage_at(0, 1), 0);
+        assert_eq!(font.glyphs[1].coverage_at(0, 1), 255);
+        assert_eq!(font.glyphs[1].coverage_at(9, 1), 255);
+    }
+
+    #[test]
+    fn psf2_truncated_or_empty() {
+        assert!(BitmapFont::load(&psf2(2, 4, (10, 2), &[0; 7])).is_err());
+        assert!(BitmapFont::load(&psf2(2, 0, (10, 2), &[])).is_err());
+        assert!(BitmapFont::load(&psf2(2, 0, (0, 0), &[])).is_err());
+        assert!(BitmapFont::load(&psf2(2, 2, (10, 2), &[0; 4])).is_err());
+        assert!(BitmapFont::load(&psf2(u32::MAX, u32::MAX, (8, 1), &[0; 8])).is_err());
+        assert!(BitmapFont::load(&psf2(1, 8, (u32::MAX, u32::MAX), &[0; 8])).is_err());
+        assert!(BitmapFont::load(&psf2(2, 4, (10, 2), &[])[..20]).is_err());
+    }
+
+    #[test]
+    fn bdf() {
+        let bdf = |bounding_box: &str, bitmap: &str| {
+            format!(
+                "STARTFONT 2.1\nFONTBOUNDINGBOX 10 2 0 0\nSTARTCHAR A\nENCODING 65\n\
+                 BBX {bounding_box}\nBITMAP\n{bitmap}\nENDCHAR\nENDFONT\n"
+            )
+        };
+
+        let font = BitmapFont::load(bdf("10 2 0 0", "FFC0\n8040").as_bytes()).unwrap();
+        assert_eq!(font.cell_size, (10, 2));
+        assert_eq!(font.glyphs[font.clusters["A"]].coverage_at(9, 0), 255);
+        assert_eq!(font.glyphs[font.clusters["A"]].coverage_at(1, 1), 0);
+
+        assert!(BitmapFont::load(bdf("10 3 0 0", "FFC0\n8040").as_bytes()).is_err());
+        assert!(BitmapFont::load(bdf("100000 2 0 0", "FFC0\n8040").as_bytes()).is_err());
+        assert!(BitmapFont::load(bdf("-1 2 0 0", "FFC0\n8040").as_bytes()).is_err());
+
+        // a Latin-1 © in a property
+        let mut latin1 = b"STARTFONT 2.1\nCOPYRIGHT \"\xa9 1999\"\n".to_vec();
+        latin1.extend_from_slice(&bdf("10 2 0 0", "FFC0\n8040").as_bytes()[14..]);
+        assert!(BitmapFont::load(&latin1).is_ok());
+    }
+
+    // PCF font with an ascent of 2 and a descent of 1, the glyphs of 'A', 'M' and 'N' and the
+    // given bitmaps, stored in `bitmap_format`
+    fn pcf(metrics: &[[i16; 5]], bitmap_format: u32, bitmaps: &[&[u8]]) -> Vec<u8> {
+        let le = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        };
+
+        let mut accelerators = le(&[0, 0, 0, 2, 1]);
+        accelerators.extend([0; 28]);
+
+        let mut metrics_table = le(&[0, metrics.len() as u32]);
+        for glyph in metrics {
+            metrics_table.extend(
+                glyph
+                    .iter()
+                    .chain(&[0])
+                    .flat_map(|value| value.to_le_bytes()),
+            );
+        }
+
+        let integer = |value: u32| match bitmap_format & Pcf::BYTE_MSB_FIRST {
+            0 => value.to_le_bytes(),
+            _ => value.to_be_bytes(),
+        };
+        let size: usize = bitmaps.iter().map(|bitmap| bitmap.len()).sum();
+        let mut bitmaps_table = bitmap_format.to_le_bytes().to_vec();
+        bitmaps_table.extend(integer(bitmaps.len() as u32));
+        let mut offset = 0;
+        for bitmap in bitmaps {
+            bitmaps_table.extend(integer(offset));
+            offset += bitmap.len() as u32;
+        }
+        bitmaps_table.extend((0..4).flat_map(|_| integer(size as u32)));
+        bitmaps_table.extend(bitmaps.concat());
+
+        // 'A' to 'N' in a single row
+        let mut encodings = le(&[0]);
+        encodings.extend(
+            [0x41u16, 0x4e, 0, 0, 0]
+                .iter()
+                .flat_map(|v| v.to_le_bytes()),
+        );
+        encodings.extend((0x41..=0x4eu16).flat_map(|code| {
+            match code {
+                0x41 => 0u16,
+                0x4d => 1,
+                0x4e => 2,
+                _ => 0xffff,
+            }
+            .to_le_bytes()
+        }));
+
+        let tables = [
+            (Pcf::BDF_ACCELERATORS, accelerators),
+            (Pcf::METRICS, metrics_table),
+            (Pcf::BDF_ENCODINGS, encodings),
+            (Pcf::BITMAPS, bitmaps_table),
+        ];
+        let mut data = PCF_MAGIC.to_vec();
+        data.extend(le(&[tables.len() as u32]));
+        let mut offset = 8 + tables.len() * 16;
+        for (kind, table) in &tables {
+            data.extend(le(&[*kind, 0, table.len() as u32, offset as u32]));
+            offset += table.len();
+        }
+        for (_, table) in tables {
+            data.extend(table);
+        }
+        data
+    }
+
+    // 'A' is 3x2, 'M' 5x2 and 'N' a double width glyph of 12x1, as left bearing, right bearing,
+    // advance, ascent and descent
+    const PCF_METRICS: [[i16; 5]; 3] = [[0, 3, 6, 2, 0], [0, 5, 6, 2, 0], [0, 12, 12, 1, 0]];
+
+    #[test]
+    fn pcf_bit_orders() {
+        let rows = |font: &BitmapFont, cluster: &str| -> Vec<String> {
+            let glyph = &font.glyphs[font.clusters[cluster]];
+            glyph
+                .coverage
+                .chunks(glyph.width as usize)
+                .map(|row| {
+                    row.iter()
+                        .map(|&c| if c != 0 { '#' } else { '.' })
+                        .collect()
+                })
+                .collect()
+        };
+
+        let fonts = [
+            // most significant byte and bit first, rows padded to 1 byte
+            (
+                Pcf::BYTE_MSB_FIRST | Pcf::BIT_MSB_FIRST,
+                [&[0xa0, 0x60][..], &[0xd8, 0x88], &[0xff, 0xf0]],
+            ),
+            // least significant byte and bit first, rows padded to 4 bytes
+            (
+                2,
+                [
+                    &[0x05, 0, 0, 0, 0x06, 0, 0, 0][..],
+                    &[0x1b, 0, 0, 0, 0x11, 0, 0, 0],
+                    &[0xff, 0x0f, 0, 0],
+                ],
+            ),
+            // most significant bit first in 4 byte units stored least significant byte first
+            (
+                Pcf::BIT_MSB_FIRST | 2 << 4 | 2,
+                [
+                    &[0, 0, 0, 0xa0, 0, 0, 0, 0x60][..],
+                    &[0, 0, 0, 0xd8, 0, 0, 0, 0x88],
+                    &[0, 0, 0xf0, 0xff],
+                ],
+            ),
+        ];
+
+        for (format, bitmaps) in fonts {
+            let font = BitmapFont::load(&pcf(&PCF_METRICS, format, &bitmaps)).unwrap();
+
+            // the cell is as wide as 'M', not as the double width glyph
+            assert_eq!(font.cell_size, (6, 3), "{format:#x}");
+            assert_eq!(rows(&font, "A"), ["#.#", ".##"], "{format:#x}");
+            assert_eq!(rows(&font, "M"), ["##.##", "#...#"], "{format:#x}");
+            assert_eq!(rows(&font, "N"), ["############"], "{format:#x}");
+            assert_eq!(font.glyphs[font.clusters["N"]].y, 1);
+            assert!(!font.clusters.contains_key("B"));
+        }
+    }
+
+    #[test]
+    fn pcf_truncated_or_too_large() {
+        let bitmaps = [&[0xa0, 0x60][..], &[0xd8, 0x88], &[0xff, 0xf0]];
+        let format = Pcf::BYTE_MSB_FIRST | Pcf::BIT_MSB_FIRST;
+
+        let font = pcf(&PCF_METRICS, format, &bitmaps);
+        assert!(BitmapFont::load(&font[..font.len() - 1]).is_err());
+
+        // the bitmap table holds fewer bytes than the metrics need
+        let mut metrics = PCF_METRICS;
+        metrics[2] = [0, 12, 12, 2, 0];
+        assert!(BitmapFont::load(&pcf(&metrics, format, &bitmaps)).is_err());
+
+        metrics[2] = [0, i16::MAX, 12, i16::MAX, 0];
+        assert!(BitmapFont::load(&pcf(&metrics, format, &bitmaps)).is_err());
+    }
+}
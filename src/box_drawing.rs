@@ -0,0 +1,395 @@
+use std::ops::Range;
+
+use crate::font::Tile;
+
+// arms of the characters from U+2500 going up, right, down and left, 1 for light lines, 2 for
+// heavy ones and 3 for double ones, "----" for the characters drawn otherwise
+const ARMS: &str = "\
+    0101 0202 1010 2020 ---- ---- ---- ---- ---- ---- ---- ---- 0110 0210 0120 0220 \
+    0011 0012 0021 0022 1100 1200 2100 2200 1001 1002 2001 2002 1110 1210 2110 1120 \
+    2120 2210 1220 2220 1011 1012 2011 1021 2021 2012 1022 2022 0111 0112 0211 0212 \
+    0121 0122 0221 0222 1101 1102 1201 1202 2101 2102 2201 2202 1111 1112 1211 1212 \
+    2111 1121 2121 2112 2211 1122 1221 2212 1222 2122 2221 2222 ---- ---- ---- ---- \
+    0303 3030 0310 0130 0330 0013 0031 0033 1300 3100 3300 1003 3001 3003 1310 3130 \
+    3330 1013 3031 3033 0313 0131 0333 1303 3101 3303 1313 3131 3333 ---- ---- ---- \
+    ---- ---- ---- ---- 0001 1000 0100 0010 0002 2000 0200 0020 0201 1020 0102 2010";
+
+// quadrants of U+2596 to U+259F, 1 upper left, 2 upper right, 4 lower left and 8 lower right
+const QUADRANTS: [u8; 10] = [4, 8, 1, 13, 9, 7, 11, 2, 6, 14];
+
+// subpixel samples per pixel along each axis, for antialiased shapes
+const SAMPLES: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Line {
+    None,
+    Light,
+    Heavy,
+    Double,
+}
+
+impl Line {
+    fn from_digit(digit: u8) -> Self {
+        match digit {
+            b'1' => Line::Light,
+            b'2' => Line::Heavy,
+            b'3' => Line::Double,
+            _ => Line::None,
+        }
+    }
+}
+
+/// Draws box drawing, block element, Braille and Powerline characters across their whole cell,
+/// so they join their neighbours whatever the font. Returns false for other characters.
+pub fn draw(cluster: &str, tile: &mut Tile) -> bool {
+    let mut chars = cluster.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return false;
+    };
+
+    let (width, height) = tile.cell_size();
+    let mut canvas = Canvas::new(width, height);
+
+    match c as u32 {
+        code @ 0x2500..=0x257f => canvas.box_drawing(code - 0x2500),
+        code @ 0x2580..=0x259f => canvas.block(code - 0x2580),
+        code @ 0x2800..=0x28ff => canvas.braille(code - 0x2800),
+        code @ 0xe0b0..=0xe0bf => canvas.powerline(code - 0xe0b0),
+        _ => return false,
+    }
+
+    tile.blit(
+        -tile.glyph_origin.0,
+        -tile.glyph_origin.1,
+        width,
+        height,
+        &canvas.pixels,
+    );
+    true
+}
+
+struct Canvas {
+    width: i32,
+    height: i32,
+    pixels: Vec<u8>,
+    // thickness of light lines
+    light: i32,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width as i32,
+            height: height as i32,
+            pixels: vec![0; (width * height) as usize],
+            light: (width.min(height) as f32 / 8.0).round().max(1.0) as i32,
+        }
+    }
+
+    fn thickness(&self, line: Line) -> i32 {
+        match line {
+            Line::None => 0,
+            Line::Light | Line::Double => self.light,
+            Line::Heavy => self.light * 2,
+        }
+    }
+
+    fn box_drawing(&mut self, index: u32) {
+        match index {
+            0x04..=0x0b | 0x4c..=0x4f => {
+                let dashes = match index {
+                    0x04..=0x07 => 3,
+                    0x08..=0x0b => 4,
+                    _ => 2,
+                };
+                let line = if index.is_multiple_of(2) {
+                    Line::Light
+                } else {
+                    Line::Heavy
+                };
+                self.dashes(dashes, line, index % 4 < 2);
+            }
+            0x6d..=0x70 => self.rounded_corner(index == 0x6e || index == 0x6f, index >= 0x6f),
+            0x71..=0x73 => {
+                let (width, height) = (self.width as f32, self.height as f32);
+                let radius = self.light as f32 / 2.0;
+                self.fill_shape(|x, y| {
+                    (index != 0x72
+                        && distance_to_segment((x, y), (width, 0.0), (0.0, height)) <= radius)
+                        || (index != 0x71
+                            && distance_to_segment((x, y), (0.0, 0.0), (width, height)) <= radius)
+                });
+            }
+            _ => {
+                let arms = ARMS
+                    .split_whitespace()
+                    .nth(index as usize)
+                    .unwrap_or_default();
+                let [up, right, down, left] = std::array::from_fn(|arm| {
+                    Line::from_digit(arms.as_bytes().get(arm).copied().unwrap_or_default())
+                });
+
+                self.arm(right, [up, down], true, true);
+                self.arm(left, [up, down], true, false);
+                self.arm(down, [left, right], false, true);
+                self.arm(up, [left, right], false, false);
+            }
+        }
+    }
+
+    // Draws a line from the middle of the cell to its right or bottom edge if `forward`, or to its
+    // left or top edge otherwise, joining the lines `across` it on either side
+    fn arm(&mut self, line: Line, across: [Line; 2], horizontal: bool, forward: bool) {
+        let (length, breadth) = if horizontal {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        };
+        let light = self.light;
+
+        // double lines have a light line on each side of the middle, with a light line of gap
+        let strokes = match line {
+            Line::None => return,
+            Line::Double => {
+                let start = (breadth - 3 * light) / 2;
+                vec![
+                    (start..start + light, 0),
+                    (start + 2 * light..start + 3 * light, 1),
+                ]
+            }
+            _ => {
+                let start = (breadth - self.thickness(line)) / 2;
+                vec![(start..start + self.thickness(line), 0)]
+            }
+        };
+
+        let double_start = (length - 3 * light) / 2;
+        let single = across
+            .iter()
+            .filter(|&&line| line == Line::Light || line == Line::Heavy)
+            .map(|&line| self.thickness(line))
+            .max();
+
+        for (stroke, side) in strokes {
+            // where the arm starts when going forward, and ends when going backward: on the line
+            // across nearest to it, on the farthest one for the outside of double corners, or
+            // covering the middle without lines across
+            let (start, end) = if across.contains(&Line::Double) {
+                if line != Line::Double || across[side] == Line::Double {
+                    (double_start + 2 * light, double_start + light)
+                } else {
+                    (double_start, double_start + 3 * light)
+                }
+            } else {
+                let thickness = match (single, line) {
+                    (Some(thickness), _) => thickness,
+                    (None, Line::Double) => 3 * light,
+                    (None, _) => stroke.len() as i32,
+                };
+                let start = (length - thickness) / 2;
+                (start, start + thickness)
+            };
+
+            let along = if forward { start..length } else { 0..end };
+
+            if horizontal {
+                self.fill(along, stroke);
+            } else {
+                self.fill(stroke, along);
+            }
+        }
+    }
+
+    fn dashes(&mut self, count: i32, line: Line, horizontal: bool) {
+        let (length, breadth) = if horizontal {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        };
+        let thickness = self.thickness(line);
+        let stroke = (breadth - thickness) / 2..(breadth - thickness) / 2 + thickness;
+
+        for dash in 0..count {
+            let (start, end) = (dash * length / count, (dash + 1) * length / count);
+            let gap = ((end - start) / 3).max(1);
+            let along = start + gap / 2..end - (gap - gap / 2);
+
+            if horizontal {
+                self.fill(along, stroke.clone());
+            } else {
+                self.fill(stroke.clone(), along);
+            }
+        }
+    }
+
+    // Draws ╭, mirrored into ╮, ╯ and ╰, as an arc between the light lines of the other characters
+    fn rounded_corner(&mut self, mirror_x: bool, mirror_y: bool) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let thickness = self.light as f32;
+
+        let mut middle = (
+            ((self.width - self.light) / 2) as f32 + thickness / 2.0,
+            ((self.height - self.light) / 2) as f32 + thickness / 2.0,
+        );
+        if mirror_x {
+            middle.0 = width - middle.0;
+        }
+        if mirror_y {
+            middle.1 = height - middle.1;
+        }
+
+        let radius = (width - middle.0).min(height - middle.1);
+        let centre = (middle.0 + radius, middle.1 + radius);
+
+        self.fill_shape(|x, y| {
+            let x = if mirror_x { width - x } else { x };
+            let y = if mirror_y { height - y } else { y };
+
+            if x >= centre.0 {
+                (y - middle.1).abs() <= thickness / 2.0
+            } else if y >= centre.1 {
+                (x - middle.0).abs() <= thickness / 2.0
+            } else {
+                let distance = ((x - centre.0).powi(2) + (y - centre.1).powi(2)).sqrt();
+                (distance - radius).abs() <= thickness / 2.0
+            }
+        });
+    }
+
+    fn block(&mut self, index: u32) {
+        let (width, height) = (self.width, self.height);
+        let eighths = |length: i32, count: i32| (length * count + 4) / 8;
+        let (middle_x, middle_y) = (width - eighths(width, 4), height - eighths(height, 4));
+
+        match index {
+            0x00 => self.fill(0..width, 0..middle_y),
+            0x01..=0x08 => self.fill(0..width, height - eighths(height, index as i32)..height),
+            0x09..=0x0f => self.fill(0..width - eighths(width, index as i32 - 8), 0..height),
+            0x10 => self.fill(middle_x..width, 0..height),
+            // shades covering a quarter, half and three quarters of the cell
+            0x11 => self.pixels.fill(64),
+            0x12 => self.pixels.fill(128),
+            0x13 => self.pixels.fill(191),
+            0x14 => self.fill(0..width, 0..height - eighths(height, 7)),
+            0x15 => self.fill(width - eighths(width, 1)..width, 0..height),
+            _ => {
+                let quadrants = QUADRANTS[index as usize - 0x16];
+                let columns = [0..middle_x, middle_x..width];
+                let rows = [0..middle_y, middle_y..height];
+
+                for quadrant in 0..4 {
+                    if quadrants & (1 << quadrant) != 0 {
+                        self.fill(columns[quadrant % 2].clone(), rows[quadrant / 2].clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // Draws the dots of a Braille pattern, numbered down the left column then the right one, with
+    // the bottom row numbered last
+    fn braille(&mut self, pattern: u32) {
+        const DOTS: [(f32, f32); 8] = [
+            (0.0, 0.0),
+            (0.0, 1.0),
+            (0.0, 2.0),
+            (1.0, 0.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 3.0),
+            (1.0, 3.0),
+        ];
+
+        let (width, height) = (self.width as f32 / 2.0, self.height as f32 / 4.0);
+        let radius = width.min(height) * 0.35;
+        let dots: Vec<_> = (0..8)
+            .filter(|dot| pattern & (1 << dot) != 0)
+            .map(|dot| {
+                let (column, row) = DOTS[dot];
+                ((column + 0.5) * width, (row + 0.5) * height)
+            })
+            .collect();
+
+        self.fill_shape(|x, y| {
+            dots.iter()
+                .any(|&(dot_x, dot_y)| (x - dot_x).powi(2) + (y - dot_y).powi(2) <= radius * radius)
+        });
+    }
+
+    // Draws the separators of Powerline and its extra symbols, from U+E0B0
+    fn powerline(&mut self, index: u32) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let radius = self.light as f32 / 2.0;
+        let line = |(x, y), from, to| distance_to_segment((x, y), from, to) <= radius;
+
+        self.fill_shape(|x, y| {
+            // from the left edge for the shapes pointing right, and from the top
+            let (u, v) = (x / width, y / height);
+            let (mirrored, flipped) = (1.0 - u, 1.0 - v);
+            let ellipse = |u: f32| (u * u + (2.0 * v - 1.0).powi(2)).sqrt();
+
+            match index {
+                0x0 => u + (2.0 * v - 1.0).abs() <= 1.0,
+                0x1 => {
+                    line((x, y), (0.0, 0.0), (width, height / 2.0))
+                        || line((x, y), (width, height / 2.0), (0.0, height))
+                }
+                0x2 => mirrored + (2.0 * v - 1.0).abs() <= 1.0,
+                0x3 => {
+                    line((x, y), (width, 0.0), (0.0, height / 2.0))
+                        || line((x, y), (0.0, height / 2.0), (width, height))
+                }
+                0x4 => ellipse(u) <= 1.0,
+                0x5 => (ellipse(u) - 1.0).abs() * width.min(height / 2.0) <= radius,
+                0x6 => ellipse(mirrored) <= 1.0,
+                0x7 => (ellipse(mirrored) - 1.0).abs() * width.min(height / 2.0) <= radius,
+                0x8 => u <= v,
+                0xa => mirrored <= v,
+                0xc => u <= flipped,
+                0xe => mirrored <= flipped,
+                0x9 | 0xf => line((x, y), (0.0, 0.0), (width, height)),
+                _ => line((x, y), (width, 0.0), (0.0, height)),
+            }
+        });
+    }
+
+    fn fill(&mut self, x: Range<i32>, y: Range<i32>) {
+        for y in y.start.max(0)..y.end.min(self.height) {
+            for x in x.start.max(0)..x.end.min(self.width) {
+                self.pixels[(y * self.width + x) as usize] = 255;
+            }
+        }
+    }
+
+    // Covers each pixel by the part of it `inside` holds for, given positions in pixels from the
+    // top left corner of the cell
+    fn fill_shape(&mut self, inside: impl Fn(f32, f32) -> bool) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let samples = (0..SAMPLES * SAMPLES)
+                    .filter(|sample| {
+                        inside(
+                            x as f32
+                                + (sample % SAMPLES) as f32 / SAMPLES as f32
+                                + 0.5 / SAMPLES as f32,
+                            y as f32
+                                + (sample / SAMPLES) as f32 / SAMPLES as f32
+                                + 0.5 / SAMPLES as f32,
+                        )
+                    })
+                    .count() as u32;
+
+                let pixel = &mut self.pixels[(y * self.width + x) as usize];
+                *pixel = (*pixel).max((samples * 255 / (SAMPLES * SAMPLES)) as u8);
+            }
+        }
+    }
+}
+
+fn distance_to_segment(point: (f32, f32), from: (f32, f32), to: (f32, f32)) -> f32 {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let along =
+        (((point.0 - from.0) * dx + (point.1 - from.1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+
+    ((point.0 - from.0 - along * dx).powi(2) + (point.1 - from.1 - along * dy).powi(2)).sqrt()
+}
@@ -1,19 +1,100 @@
+use crate::palette::{Color, Palette};
+
+// the colours are encoded with Color::encode, and resolved against the palette when uploaded
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Character {
     pub bg: [f32; 3],
-    _padding: u32,
+    // slot of the glyph in the atlas, set when the cell is rendered
+    pub glyph: u32,
     pub fg: [f32; 3],
     pub code: u32,
+    pub underline_color: [f32; 3],
+    pub flags: u32,
 }
 
 impl Character {
-    pub fn new(bg: [f32; 3], fg: [f32; 3], code: u32) -> Self {
+    pub const BOLD: u32 = 1 << 0;
+    pub const FAINT: u32 = 1 << 1;
+    pub const ITALIC: u32 = 1 << 2;
+    // 3 bits holding the underline style: none, single, double, curly, dotted or dashed
+    pub const UNDERLINE_SHIFT: u32 = 3;
+    pub const UNDERLINE_MASK: u32 = 0b111 << Self::UNDERLINE_SHIFT;
+    pub const STRIKETHROUGH: u32 = 1 << 6;
+    pub const OVERLINE: u32 = 1 << 7;
+    pub const INVERSE: u32 = 1 << 8;
+    pub const HIDDEN: u32 = 1 << 9;
+    pub const BLINK: u32 = 1 << 10;
+    // `underline_color` is used instead of the foreground color
+    pub const UNDERLINE_COLOR: u32 = 1 << 11;
+    // first cell of a character two cells wide, and the cell after it
+    pub const WIDE: u32 = 1 << 12;
+    pub const WIDE_SPACER: u32 = 1 << 13;
+    // blank cell ending a row because the wide character after it didn't fit, dropped when
+    // the row is reflowed
+    pub const WIDE_PADDING: u32 = 1 << 14;
+
+    pub const UNDERLINE_NONE: u32 = 0;
+    pub const UNDERLINE_SINGLE: u32 = 1;
+    pub const UNDERLINE_DOUBLE: u32 = 2;
+
+    pub fn new(bg: Color, fg: Color, code: u32) -> Self {
         Self {
-            bg,
-            _padding: 0,
-            fg,
+            bg: bg.encode(),
+            glyph: 0,
+            fg: fg.encode(),
             code,
+            underline_color: fg.encode(),
+            flags: 0,
+        }
+    }
+
+    pub fn with_attributes(self, flags: u32, underline_color: Option<Color>) -> Self {
+        match underline_color {
+            Some(underline_color) => Self {
+                underline_color: underline_color.encode(),
+                flags: flags | Self::UNDERLINE_COLOR,
+                ..self
+            },
+            None => Self {
+                flags: flags & !Self::UNDERLINE_COLOR,
+                ..self
+            },
+        }
+    }
+
+    /// Returns the character with the colours it takes from `palette` as RGB, as drawn.
+    pub fn resolve(self, palette: &Palette) -> Self {
+        Self {
+            bg: palette.background(Color::decode(self.bg)),
+            fg: palette.foreground(Color::decode(self.fg)),
+            underline_color: palette.foreground(Color::decode(self.underline_color)),
+            ..self
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::rgb;
+
+    #[test]
+    fn colors_follow_the_palette() {
+        let character = Character::new(Color::Indexed(1), Color::Default, 'A' as u32)
+            .with_attributes(0, Some(Color::Rgb(rgb(1, 2, 3))));
+
+        let mut palette = Palette::default();
+        let resolved = character.resolve(&palette);
+        assert_eq!(resolved.bg, palette.colors[1]);
+        assert_eq!(resolved.fg, palette.foreground);
+        assert_eq!(resolved.underline_color, rgb(1, 2, 3));
+
+        palette.colors[1] = rgb(0, 0, 255);
+        palette.foreground = rgb(255, 255, 0);
+        let resolved = character.resolve(&palette);
+        assert_eq!(resolved.bg, rgb(0, 0, 255));
+        assert_eq!(resolved.fg, rgb(255, 255, 0));
+        assert_eq!(resolved.underline_color, rgb(1, 2, 3));
+    }
+}
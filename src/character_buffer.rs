@@ -1,66 +1,1065 @@
-use std::ops::Range;
+use std::{collections::VecDeque, ops::Range};
 
-use crate::{character::Character, globals};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::{character::Character, globals, grapheme::Graphemes, palette::Palette};
+
+const TAB_WIDTH: usize = 8;
+
+struct ScrollbackLine {
+    cells: Vec<Character>,
+    wrapped: bool,
+}
 
 pub struct CharacterBuffer {
     characters: Vec<Character>,
+    // maps each row of the grid to a row of `characters`, so scrolling only moves indices
+    rows: Vec<u32>,
     pub bounds: (u32, u32),
     pub cursor_position: (u32, u32),
 
-    pending_change: Option<Range<usize>>,
+    scroll_region: Range<u32>,
+    origin_mode: bool,
+    auto_wrap: bool,
+    wrap_pending: bool,
+    tab_stops: Vec<bool>,
+    // whether each row of `characters` was auto-wrapped onto the next one
+    wrapped: Vec<bool>,
+
+    // the alternate screen uses the other half of `rows`, and has no scrollback
+    alternate: bool,
+    // bounds and cursor the primary screen was left with, to resize it to the bounds it comes
+    // back to after the alternate screen
+    primary_bounds: (u32, u32),
+    primary_cursor_position: (u32, u32),
+
+    scrollback: VecDeque<ScrollbackLine>,
+    scrollback_size: usize,
+    // number of lines the view is scrolled back into `scrollback`
+    display_offset: u32,
+
+    // character erased cells are filled with
+    blank: Character,
+    graphemes: Graphemes,
+
+    // changed columns of each row of `characters`
+    pending_change: Vec<Option<Range<u32>>>,
+    rows_changed: bool,
 }
 
 impl CharacterBuffer {
-    pub fn new(bounds: (u32, u32)) -> Self {
+    pub fn new(bounds: (u32, u32), scrollback_size: usize, blank: Character) -> Self {
+        let bounds = Self::clamp_bounds(bounds);
+
         Self {
-            characters: vec![
-                Character::new([0.0, 0.0, 0.0], [1.0, 1.0, 1.0], 0);
-                globals::CHARACTER_BUFFER_SIZE
-            ],
+            characters: vec![blank; globals::CHARACTER_BUFFER_SIZE],
+            rows: (0..globals::CHARACTER_BUFFER_HEIGHT as u32).collect(),
             bounds,
             cursor_position: (0, 0),
 
-            pending_change: None,
+            scroll_region: 0..bounds.1,
+            origin_mode: false,
+            auto_wrap: true,
+            wrap_pending: false,
+            tab_stops: (0..globals::CHARACTER_BUFFER_WIDTH)
+                .map(|x| x % TAB_WIDTH == 0)
+                .collect(),
+            wrapped: vec![false; globals::CHARACTER_BUFFER_HEIGHT],
+
+            alternate: false,
+            primary_bounds: bounds,
+            primary_cursor_position: (0, 0),
+
+            scrollback: VecDeque::new(),
+            scrollback_size,
+            display_offset: 0,
+
+            blank,
+            graphemes: Graphemes::default(),
+
+            // everything is uploaded at first
+            pending_change: vec![Some(0..bounds.0); globals::CHARACTER_BUFFER_HEIGHT],
+            rows_changed: false,
+        }
+    }
+
+    pub fn set_bounds(&mut self, bounds: (u32, u32)) {
+        let old_bounds = self.bounds;
+        let bounds = Self::clamp_bounds(bounds);
+
+        if bounds != old_bounds && bounds.0 > 0 && bounds.1 > 0 && !self.alternate {
+            self.reflow(bounds);
+        } else {
+            self.bounds = bounds;
+
+            // cells uncovered by growing the grid may still hold old content
+            if self.bounds.0 > old_bounds.0 {
+                for y in 0..old_bounds.1.min(self.bounds.1) {
+                    self.clear_columns(y, old_bounds.0..self.bounds.0);
+                }
+            }
+            if self.bounds.1 > old_bounds.1 {
+                self.clear_rows(old_bounds.1..self.bounds.1);
+            }
+        }
+
+        self.rows_changed = true;
+        self.fill_scrollback_view();
+
+        self.scroll_region = 0..self.bounds.1;
+        self.wrap_pending = false;
+        self.cursor_position = (
+            self.cursor_position.0.min(self.bounds.0.saturating_sub(1)),
+            self.cursor_position.1.min(self.bounds.1.saturating_sub(1)),
+        );
+    }
+
+    /// Prints `c` at the cursor, over two cells if it's `wide`.
+    pub fn push_char(&mut self, c: Character, wide: bool) {
+        let width = if wide { 2 } else { 1 };
+
+        if width > self.bounds.0 {
+            return;
+        }
+
+        if self.wrap_pending && self.auto_wrap {
+            self.wrap_line();
+        }
+
+        if self.cursor_position.0 + width > self.bounds.0 {
+            if !self.auto_wrap {
+                return;
+            }
+
+            // a wide character doesn't fit in the last column
+            let (x, y) = self.cursor_position;
+            self.clear_columns(y, x..self.bounds.0);
+            let index = self.vec_coord(x, y);
+            self.characters[index].flags |= Character::WIDE_PADDING;
+            self.wrap_line();
+        }
+
+        let (x, y) = self.cursor_position;
+        self.split_wide(y, x);
+        self.split_wide(y, x + width);
+
+        let index = self.vec_coord(x, y);
+        if wide {
+            let mut spacer = c;
+            spacer.code = 0;
+            spacer.flags |= Character::WIDE_SPACER;
+
+            self.characters[index] = c;
+            self.characters[index].flags |= Character::WIDE;
+            self.characters[index + 1] = spacer;
+        } else {
+            self.characters[index] = c;
+        }
+        self.mark_changed(y, x..(x + width));
+
+        if x + width >= self.bounds.0 {
+            self.cursor_position.0 = self.bounds.0 - 1;
+            self.wrap_pending = true;
+        } else {
+            self.cursor_position.0 += width;
         }
     }
 
-    pub fn push_char(&mut self, c: Character) {
-        let index = Self::vec_coord(self.cursor_position);
-        self.characters[index] = c;
-        self.cursor_position.0 += 1;
+    /// Appends `c` to the grapheme cluster of the last printed cell if it's zero width and extends
+    /// it, like a combining mark or a zero width joiner does. Returns whether it was appended.
+    pub fn combine(&mut self, c: char) -> bool {
+        // chars with a width of their own take their own cells, as the shell's wcwidth counts them
+        if c.is_ascii() || c.width() != Some(0) {
+            return false;
+        }
+
+        let Some((x, y)) = self.previous_cell() else {
+            return false;
+        };
+
+        let index = self.vec_coord(x, y);
+        let mut cluster = self.graphemes.get(self.characters[index].code).into_owned();
+        cluster.push(c);
+
+        if cluster.graphemes(true).count() != 1 {
+            return false;
+        }
+
+        self.characters[index].code = self.graphemes.intern(&cluster);
+        self.mark_changed(y, x..(x + 1));
+
+        if self.graphemes.needs_collect() {
+            self.collect_graphemes();
+        }
+        true
+    }
+
+    pub fn is_alternate(&self) -> bool {
+        self.alternate
+    }
+
+    /// Switches between the primary and the alternate screen. The alternate screen is cleared
+    /// when entering it, as its rows are used to show the scrollback of the primary one meanwhile.
+    /// The primary screen is resized when leaving it if the bounds changed in between.
+    pub fn set_alternate(&mut self, alternate: bool) {
+        if alternate == self.alternate {
+            return;
+        }
+
+        self.scroll_view_to_bottom();
+        if alternate {
+            self.primary_bounds = self.bounds;
+            self.primary_cursor_position = self.cursor_position;
+        }
+
+        self.alternate = alternate;
+        self.rows.rotate_left(globals::CHARACTER_BUFFER_HEIGHT / 2);
+        self.scroll_region = 0..self.bounds.1;
+        self.wrap_pending = false;
+        self.rows_changed = true;
+
+        if alternate {
+            self.clear_rows(0..self.bounds.1);
+        } else if self.primary_bounds != self.bounds {
+            let bounds = self.bounds;
+            self.bounds = self.primary_bounds;
+            self.cursor_position = self.primary_cursor_position;
+            self.set_bounds(bounds);
+        }
+    }
+
+    /// Sets the number of lines kept in the scrollback, dropping the oldest ones past it.
+    pub fn set_scrollback_size(&mut self, scrollback_size: usize) {
+        self.scrollback_size = scrollback_size;
+
+        if self.scrollback.len() > scrollback_size {
+            self.scrollback
+                .drain(..self.scrollback.len() - scrollback_size);
+            self.display_offset = self.display_offset.min(scrollback_size as u32);
+            self.rows_changed = true;
+            self.fill_scrollback_view();
+        }
+    }
+
+    pub fn set_blank(&mut self, blank: Character) {
+        self.blank = blank;
+    }
+
+    pub fn origin_mode(&self) -> bool {
+        self.origin_mode
+    }
+
+    // DECOM
+    pub fn set_origin_mode(&mut self, origin_mode: bool) {
+        self.origin_mode = origin_mode;
+        self.goto(0, 0);
+    }
+
+    // DECAWM
+    pub fn set_auto_wrap(&mut self, auto_wrap: bool) {
+        self.auto_wrap = auto_wrap;
+        self.wrap_pending = false;
+    }
+
+    pub fn set_cursor_position(&mut self, x: u32, y: u32) {
+        self.wrap_pending = false;
+        self.cursor_position = (
+            x.min(self.bounds.0.saturating_sub(1)),
+            y.min(self.bounds.1.saturating_sub(1)),
+        );
+    }
+
+    /// Moves the cursor to `x`, `y`, relative to the scroll region in origin mode.
+    pub fn goto(&mut self, x: u32, y: u32) {
+        if self.origin_mode {
+            let y = (self.scroll_region.start + y).min(self.scroll_region.end.saturating_sub(1));
+            self.set_cursor_position(x, y);
+        } else {
+            self.set_cursor_position(x, y);
+        }
+    }
+
+    pub fn goto_column(&mut self, x: u32) {
+        self.set_cursor_position(x, self.cursor_position.1);
+    }
 
-        if self.cursor_position.0 >= self.bounds.0 {
+    pub fn goto_line(&mut self, y: u32) {
+        self.goto(self.cursor_position.0, y);
+    }
+
+    /// Moves the cursor by `x`, `y`, stopping at the scroll region margins when inside of it.
+    pub fn move_cursor(&mut self, x: i32, y: i32) {
+        let (cursor_x, cursor_y) = self.cursor_position;
+        let (top, bottom) = if self.scroll_region.contains(&cursor_y) {
+            (self.scroll_region.start, self.scroll_region.end)
+        } else {
+            (0, self.bounds.1)
+        };
+
+        let new_y = (cursor_y as i64 + y as i64)
+            .clamp(top as i64, bottom.saturating_sub(1).max(top) as i64);
+        let new_x = (cursor_x as i64 + x as i64).max(0);
+        self.set_cursor_position(new_x as u32, new_y as u32);
+    }
+
+    // ED
+    pub fn erase_in_display(&mut self, mode: u16) {
+        let y = self.cursor_position.1;
+
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                self.clear_rows((y + 1)..self.bounds.1);
+            }
+            1 => {
+                self.clear_rows(0..y);
+                self.erase_in_line(1);
+            }
+            2 => self.clear_rows(0..self.bounds.1),
+            3 => {
+                self.scroll_view_to_bottom();
+                self.scrollback.clear();
+            }
+            _ => {}
+        }
+    }
+
+    // EL
+    pub fn erase_in_line(&mut self, mode: u16) {
+        let (x, y) = self.cursor_position;
+        let end = (x + 1).min(self.bounds.0);
+
+        match mode {
+            0 => self.clear_columns(y, x.min(self.bounds.0)..self.bounds.0),
+            1 => self.clear_columns(y, 0..end),
+            2 => self.clear_columns(y, 0..self.bounds.0),
+            _ => return,
+        }
+
+        self.wrapped[self.rows[y as usize] as usize] = false;
+    }
+
+    // ECH
+    pub fn erase_characters(&mut self, count: u32) {
+        let (x, y) = self.cursor_position;
+        self.wrap_pending = false;
+        self.clear_columns(y, x..(x.saturating_add(count)).min(self.bounds.0));
+    }
+
+    // ICH
+    pub fn insert_blanks(&mut self, count: u32) {
+        let (x, y) = self.cursor_position;
+        let count = count.min(self.bounds.0 - x);
+        let start = self.vec_coord(x, y);
+        let end = self.vec_coord(self.bounds.0, y);
+
+        self.wrap_pending = false;
+        self.split_wide(y, x);
+        self.split_wide(y, self.bounds.0 - count);
+        self.characters[start..end].rotate_right(count as usize);
+        self.clear_columns(y, x..(x + count));
+        self.mark_changed(y, x..self.bounds.0);
+    }
+
+    // DCH
+    pub fn delete_characters(&mut self, count: u32) {
+        let (x, y) = self.cursor_position;
+        let count = count.min(self.bounds.0 - x);
+        let start = self.vec_coord(x, y);
+        let end = self.vec_coord(self.bounds.0, y);
+
+        self.wrap_pending = false;
+        self.split_wide(y, x);
+        self.split_wide(y, x + count);
+        self.characters[start..end].rotate_left(count as usize);
+        self.clear_columns(y, (self.bounds.0 - count)..self.bounds.0);
+        self.mark_changed(y, x..self.bounds.0);
+    }
+
+    // IL
+    pub fn insert_lines(&mut self, count: u32) {
+        let y = self.cursor_position.1;
+
+        if self.scroll_region.contains(&y) {
+            self.scroll_rows_down(y..self.scroll_region.end, count);
+            self.carriage_return();
+        }
+    }
+
+    // DL
+    pub fn delete_lines(&mut self, count: u32) {
+        let y = self.cursor_position.1;
+
+        if self.scroll_region.contains(&y) {
+            self.scroll_rows_up(y..self.scroll_region.end, count);
+            self.carriage_return();
+        }
+    }
+
+    pub fn line_feed(&mut self) {
+        self.wrap_pending = false;
+
+        if self.cursor_position.1 + 1 == self.scroll_region.end {
+            self.scroll_up(1);
+        } else if self.cursor_position.1 + 1 < self.bounds.1 {
             self.cursor_position.1 += 1;
-            self.cursor_position.0 = 0;
         }
+    }
+
+    pub fn reverse_line_feed(&mut self) {
+        self.wrap_pending = false;
+
+        if self.cursor_position.1 == self.scroll_region.start {
+            self.scroll_down(1);
+        } else {
+            self.cursor_position.1 = self.cursor_position.1.saturating_sub(1);
+        }
+    }
+
+    pub fn carriage_return(&mut self) {
+        self.wrap_pending = false;
+        self.cursor_position.0 = 0;
+    }
+
+    pub fn backspace(&mut self) {
+        self.wrap_pending = false;
+        self.cursor_position.0 = self.cursor_position.0.saturating_sub(1);
+    }
+
+    pub fn tab(&mut self) {
+        let last_column = self.bounds.0.saturating_sub(1);
+
+        while self.cursor_position.0 < last_column {
+            self.cursor_position.0 += 1;
+
+            if self.tab_stops[self.cursor_position.0 as usize] {
+                break;
+            }
+        }
+    }
+
+    pub fn set_tab_stop(&mut self) {
+        self.tab_stops[self.cursor_position.0 as usize] = true;
+    }
+
+    pub fn clear_tab_stop(&mut self) {
+        self.tab_stops[self.cursor_position.0 as usize] = false;
+    }
+
+    pub fn clear_all_tab_stops(&mut self) {
+        self.tab_stops.fill(false);
+    }
+
+    /// Sets the scroll region to rows `top..bottom` and moves the cursor home, ignoring invalid regions.
+    pub fn set_scroll_region(&mut self, top: u32, bottom: u32) {
+        let bottom = bottom.min(self.bounds.1);
+
+        if top + 1 < bottom {
+            self.scroll_region = top..bottom;
+            self.goto(0, 0);
+        }
+    }
+
+    /// Scrolls the scroll region up by `count` lines, inserting blank lines at the bottom.
+    /// Lines leaving the top of the screen are kept in the scrollback.
+    pub fn scroll_up(&mut self, count: u32) {
+        let Range { start, end } = self.scroll_region.clone();
+        let count = count.min(end - start);
+
+        if start == 0 && self.scrollback_size > 0 && !self.alternate {
+            for y in 0..count {
+                self.push_scrollback(y);
+            }
+        }
+
+        self.scroll_rows_up(start..end, count);
+    }
+
+    /// Scrolls the scroll region down by `count` lines, inserting blank lines at the top.
+    pub fn scroll_down(&mut self, count: u32) {
+        self.scroll_rows_down(self.scroll_region.clone(), count);
+    }
+
+    /// Moves the view `lines` lines back into the scrollback, or forward if negative.
+    /// Returns whether the view moved.
+    pub fn scroll_view(&mut self, lines: i32) -> bool {
+        if self.alternate {
+            return false;
+        }
+
+        let display_offset = (self.display_offset as i64 + lines as i64)
+            .clamp(0, self.scrollback.len() as i64) as u32;
+
+        if display_offset == self.display_offset {
+            return false;
+        }
+
+        self.display_offset = display_offset;
+        self.rows_changed = true;
+        self.fill_scrollback_view();
+        true
+    }
+
+    pub fn scroll_view_to_bottom(&mut self) -> bool {
+        self.scroll_view(-(self.display_offset as i32))
+    }
 
-        match &mut self.pending_change {
+    /// Marks all the cells as changed, so they are uploaded again.
+    pub fn mark_all_changed(&mut self) {
+        for row in 0..globals::CHARACTER_BUFFER_HEIGHT as u32 {
+            self.mark_row_changed(row, 0..self.bounds.0);
+        }
+    }
+
+    /// Uploads the changed cells with their colours resolved against `palette`, and the rows in
+    /// view if they changed.
+    pub fn write_changes(
+        &mut self,
+        queue: &wgpu::Queue,
+        palette: &Palette,
+        character_buffer: &wgpu::Buffer,
+        row_buffer: &wgpu::Buffer,
+    ) {
+        let width = globals::CHARACTER_BUFFER_WIDTH;
+        let character_size = std::mem::size_of::<Character>();
+
+        for (row, change) in self.pending_change.iter_mut().enumerate() {
+            if let Some(columns) = change.take() {
+                let start = row * width + columns.start as usize;
+                let end = row * width + columns.end as usize;
+                let characters: Vec<Character> = self.characters[start..end]
+                    .iter()
+                    .map(|character| character.resolve(palette))
+                    .collect();
+
+                queue.write_buffer(
+                    character_buffer,
+                    (start * character_size) as u64,
+                    bytemuck::cast_slice(&characters),
+                );
+            }
+        }
+
+        if self.rows_changed {
+            queue.write_buffer(row_buffer, 0, bytemuck::cast_slice(&self.display_rows()));
+            self.rows_changed = false;
+        }
+    }
+
+    /// Returns whether the rows in view changed since the last `write_changes`.
+    pub fn rows_changed(&self) -> bool {
+        self.rows_changed
+    }
+
+    /// Sets the glyph of the cells in view to the one `glyph` gives for their code and grapheme
+    /// cluster. Only the changed cells are updated, unless `all` is set.
+    pub fn update_glyphs(&mut self, all: bool, mut glyph: impl FnMut(u32, &str) -> u32) {
+        let width = globals::CHARACTER_BUFFER_WIDTH;
+
+        for row in self.display_rows() {
+            let columns = match &self.pending_change[row as usize] {
+                _ if all => 0..self.bounds.0,
+                Some(columns) => columns.clone(),
+                None => continue,
+            };
+            let mut changed: Option<Range<u32>> = None;
+
+            for x in columns {
+                let cell = &mut self.characters[row as usize * width + x as usize];
+                let slot = match cell.code {
+                    0 => 0,
+                    code => glyph(code, &self.graphemes.get(code)),
+                };
+
+                if cell.glyph != slot {
+                    cell.glyph = slot;
+                    changed = Some(changed.map_or(x..(x + 1), |changed| changed.start..(x + 1)));
+                }
+            }
+
+            if let Some(columns) = changed {
+                self.mark_row_changed(row, columns);
+            }
+        }
+    }
+
+    /// Returns the position of the cursor in the view, if it's not scrolled out of it.
+    pub fn cursor_in_view(&self) -> Option<(u32, u32)> {
+        let y = self.cursor_position.1 + self.display_offset;
+        (y < self.bounds.1).then_some((self.cursor_position.0, y))
+    }
+
+    pub fn rows(&self) -> &[u32] {
+        &self.rows
+    }
+
+    fn wrap_line(&mut self) {
+        self.wrapped[self.rows[self.cursor_position.1 as usize] as usize] = true;
+        self.carriage_return();
+        self.line_feed();
+    }
+
+    // Drops the interned clusters no longer printed on either screen or in the scrollback
+    fn collect_graphemes(&mut self) {
+        let scrollback = self.scrollback.iter().flat_map(|line| &line.cells);
+        let live = self.characters.iter().chain(scrollback).map(|c| c.code);
+        self.graphemes.retain(live);
+    }
+
+    // Cell holding the cluster printed right before the cursor
+    fn previous_cell(&self) -> Option<(u32, u32)> {
+        let (x, y) = self.cursor_position;
+        let mut x = if self.wrap_pending {
+            x
+        } else {
+            x.checked_sub(1)?
+        };
+
+        if self.characters[self.vec_coord(x, y)].flags & Character::WIDE_SPACER != 0 {
+            x = x.checked_sub(1)?;
+        }
+
+        (self.characters[self.vec_coord(x, y)].code != 0).then_some((x, y))
+    }
+
+    // Erases the wide character crossing the boundary before column `x`, if any, so neither of
+    // its halves is left alone
+    fn split_wide(&mut self, y: u32, x: u32) {
+        if x == 0 || x >= self.bounds.0 {
+            return;
+        }
+
+        let index = self.vec_coord(x, y);
+        if self.characters[index].flags & Character::WIDE_SPACER != 0 {
+            self.characters[(index - 1)..=index].fill(self.blank);
+            self.mark_changed(y, (x - 1)..(x + 1));
+        }
+    }
+
+    // Rows of `characters` showing each row of the view, scrollback lines included
+    fn display_rows(&self) -> Vec<u32> {
+        (0..self.bounds.1)
+            .map(|y| match y.checked_sub(self.display_offset) {
+                Some(grid_y) => self.rows[grid_y as usize],
+                None => self.scrollback_row(y),
+            })
+            .collect()
+    }
+
+    // Rows of `characters` past the grid are never used by it, and hold scrollback lines in view
+    fn scrollback_row(&self, y: u32) -> u32 {
+        self.rows[globals::CHARACTER_BUFFER_HEIGHT - 1 - y as usize]
+    }
+
+    fn push_scrollback(&mut self, y: u32) {
+        let start = self.vec_coord(0, y);
+        let line = ScrollbackLine {
+            cells: self.characters[start..(start + self.bounds.0 as usize)].to_vec(),
+            wrapped: self.wrapped[self.rows[y as usize] as usize],
+        };
+
+        if self.scrollback.len() >= self.scrollback_size {
+            self.scrollback.pop_front();
+        }
+
+        self.scrollback.push_back(line);
+
+        if self.display_offset > 0 {
+            // keep the view on the same lines while output scrolls by
+            self.display_offset = (self.display_offset + 1).min(self.scrollback.len() as u32);
+            self.rows_changed = true;
+            self.fill_scrollback_view();
+        }
+    }
+
+    fn fill_scrollback_view(&mut self) {
+        let width = globals::CHARACTER_BUFFER_WIDTH;
+        let in_view = self.display_offset.min(self.bounds.1);
+        let first_line = self.scrollback.len() - self.display_offset as usize;
+
+        for y in 0..in_view {
+            let row = self.scrollback_row(y);
+            let start = row as usize * width;
+            let line = &self.scrollback[first_line + y as usize].cells;
+            let length = line.len().min(self.bounds.0 as usize);
+
+            self.characters[start..(start + length)].copy_from_slice(&line[..length]);
+            self.characters[(start + length)..(start + self.bounds.0 as usize)].fill(self.blank);
+            self.mark_row_changed(row, 0..self.bounds.0);
+        }
+    }
+
+    // Rewraps the scrollback and the grid to a new width, joining the rows that were auto-wrapped
+    fn reflow(&mut self, bounds: (u32, u32)) {
+        let old_width = self.bounds.0 as usize;
+        let width = bounds.0 as usize;
+        let height = bounds.1 as usize;
+
+        let grid_lines: Vec<ScrollbackLine> = (0..self.bounds.1)
+            .map(|y| {
+                let start = self.vec_coord(0, y);
+                ScrollbackLine {
+                    cells: self.characters[start..(start + old_width)].to_vec(),
+                    wrapped: self.wrapped[self.rows[y as usize] as usize],
+                }
+            })
+            .collect();
+        let cursor_row = self.scrollback.len() + self.cursor_position.1 as usize;
+
+        // logical lines, with the cursor as a line index and an offset in that line
+        let mut lines: Vec<Vec<Character>> = Vec::new();
+        let mut current = Vec::new();
+        let mut cursor = (0, 0);
+        let mut used_lines = 0;
+
+        for (index, mut line) in std::mem::take(&mut self.scrollback)
+            .into_iter()
+            .chain(grid_lines)
+            .enumerate()
+        {
+            if index == cursor_row {
+                cursor = (lines.len(), current.len() + self.cursor_position.0 as usize);
+                used_lines = lines.len() + 1;
+            }
+
+            line.cells
+                .retain(|c| c.flags & Character::WIDE_PADDING == 0);
+
+            if !line.wrapped {
+                let length = line
+                    .cells
+                    .iter()
+                    .rposition(|c| !Self::is_blank(c))
+                    .map_or(0, |last| last + 1);
+                line.cells.truncate(length);
+            }
+
+            current.extend(line.cells);
+
+            if !line.wrapped {
+                if !current.is_empty() {
+                    used_lines = used_lines.max(lines.len() + 1);
+                }
+                lines.push(std::mem::take(&mut current));
+            }
+        }
+        if !current.is_empty() {
+            lines.push(current);
+            used_lines = lines.len();
+        }
+        lines.truncate(used_lines);
+
+        let mut rows: Vec<ScrollbackLine> = Vec::new();
+        let mut cursor_position = (0, 0);
+
+        for (index, line) in lines.into_iter().enumerate() {
+            let first_row = rows.len();
+            let chunks = Self::wrap_points(&line, width);
+
+            rows.extend(chunks.iter().enumerate().map(|(i, chunk)| ScrollbackLine {
+                cells: line[chunk.clone()].to_vec(),
+                wrapped: i + 1 < chunks.len(),
+            }));
+
+            if index == cursor.0 {
+                let row = chunks
+                    .iter()
+                    .position(|chunk| cursor.1 < chunk.end)
+                    .unwrap_or(chunks.len() - 1);
+                let column = cursor.1 - chunks[row].start;
+                cursor_position = (column.min(width - 1), first_row + row);
+            }
+        }
+
+        let top = rows.len().saturating_sub(height).min(cursor_position.1);
+        let mut grid_rows = rows.split_off(top);
+        grid_rows.truncate(height);
+
+        let scrollback_start = rows.len().saturating_sub(self.scrollback_size);
+        self.scrollback = rows.into_iter().skip(scrollback_start).collect();
+        self.display_offset = 0;
+
+        self.bounds = bounds;
+        self.cursor_position = (cursor_position.0 as u32, (cursor_position.1 - top) as u32);
+
+        for y in 0..height {
+            let row = self.rows[y];
+            let start = row as usize * globals::CHARACTER_BUFFER_WIDTH;
+            let line = grid_rows.get(y);
+            let length = line.map_or(0, |line| line.cells.len());
+
+            if let Some(line) = line {
+                self.characters[start..(start + length)].copy_from_slice(&line.cells);
+            }
+            self.characters[(start + length)..(start + width)].fill(self.blank);
+            self.wrapped[row as usize] = line.is_some_and(|line| line.wrapped);
+            if self.wrapped[row as usize] && length < width {
+                self.characters[start + length].flags |= Character::WIDE_PADDING;
+            }
+            self.mark_row_changed(row, 0..bounds.0);
+        }
+    }
+
+    // Splits a logical line into rows of at most `width` cells, without splitting wide characters
+    fn wrap_points(line: &[Character], width: usize) -> Vec<Range<usize>> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while line.len() - start > width {
+            let mut end = start + width;
+            if line[end].flags & Character::WIDE_SPACER != 0 && end - 1 > start {
+                end -= 1;
+            }
+
+            chunks.push(start..end);
+            start = end;
+        }
+
+        chunks.push(start..line.len());
+        chunks
+    }
+
+    fn scroll_rows_up(&mut self, rows: Range<u32>, count: u32) {
+        let Range { start, end } = rows;
+        let count = count.min(end - start);
+
+        self.rows[start as usize..end as usize].rotate_left(count as usize);
+        self.clear_rows((end - count)..end);
+        self.rows_changed = true;
+    }
+
+    fn scroll_rows_down(&mut self, rows: Range<u32>, count: u32) {
+        let Range { start, end } = rows;
+        let count = count.min(end - start);
+
+        self.rows[start as usize..end as usize].rotate_right(count as usize);
+        self.clear_rows(start..(start + count));
+        self.rows_changed = true;
+    }
+
+    fn clear_rows(&mut self, rows: Range<u32>) {
+        for y in rows {
+            self.wrapped[self.rows[y as usize] as usize] = false;
+            self.clear_columns(y, 0..self.bounds.0);
+        }
+    }
+
+    fn clear_columns(&mut self, y: u32, columns: Range<u32>) {
+        self.split_wide(y, columns.start);
+        self.split_wide(y, columns.end);
+
+        let start = self.vec_coord(columns.start, y);
+        let end = self.vec_coord(columns.end, y);
+        self.characters[start..end].fill(self.blank);
+        self.mark_changed(y, columns);
+    }
+
+    fn is_blank(c: &Character) -> bool {
+        c.code == 0 && c.flags & Character::WIDE_SPACER == 0
+    }
+
+    fn mark_changed(&mut self, y: u32, columns: Range<u32>) {
+        self.mark_row_changed(self.rows[y as usize], columns);
+    }
+
+    fn mark_row_changed(&mut self, row: u32, columns: Range<u32>) {
+        match &mut self.pending_change[row as usize] {
             Some(range) => {
-                if index >= range.end {
-                    range.end = index + 1;
-                } else if index < range.start {
-                    range.start = index;
+                range.start = range.start.min(columns.start);
+                range.end = range.end.max(columns.end);
+            }
+            change => *change = Some(columns),
+        }
+    }
+
+    fn clamp_bounds(bounds: (u32, u32)) -> (u32, u32) {
+        (
+            bounds.0.min(globals::CHARACTER_BUFFER_WIDTH as u32),
+            // the other half of the rows is kept to display the scrollback
+            bounds.1.min(globals::CHARACTER_BUFFER_HEIGHT as u32 / 2),
+        )
+    }
+
+    fn vec_coord(&self, x: u32, y: u32) -> usize {
+        self.rows[y as usize] as usize * globals::CHARACTER_BUFFER_WIDTH + x as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Color;
+
+    fn buffer(bounds: (u32, u32)) -> CharacterBuffer {
+        CharacterBuffer::new(
+            bounds,
+            100,
+            Character::new(Color::Default, Color::Default, 0),
+        )
+    }
+
+    fn print(buffer: &mut CharacterBuffer, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    buffer.carriage_return();
+                    buffer.line_feed();
+                }
+                c => buffer.push_char(
+                    Character::new(Color::Default, Color::Default, c as u32),
+                    false,
+                ),
+            }
+        }
+    }
+
+    fn screen(buffer: &CharacterBuffer) -> Vec<String> {
+        (0..buffer.bounds.1 as usize)
+            .map(|y| {
+                let start = buffer.rows()[y] as usize * globals::CHARACTER_BUFFER_WIDTH;
+                let cells = &buffer.characters[start..(start + buffer.bounds.0 as usize)];
+                let line: String = cells
+                    .iter()
+                    .map(|c| char::from_u32(c.code).filter(|&c| c != '\0').unwrap_or(' '))
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reflow_narrower_then_wider() {
+        let mut buffer = buffer((10, 3));
+        print(&mut buffer, "abcdefghijklmno\nxyz");
+
+        buffer.set_bounds((4, 3));
+        assert_eq!(screen(&buffer), ["ijkl", "mno", "xyz"]);
+        assert_eq!(buffer.cursor_position, (3, 2));
+
+        buffer.set_bounds((10, 3));
+        assert_eq!(screen(&buffer), ["abcdefghij", "klmno", "xyz"]);
+        assert_eq!(buffer.cursor_position, (3, 2));
+    }
+
+    #[test]
+    fn reflow_keeps_wide_characters_whole() {
+        let mut buffer = buffer((6, 3));
+        print(&mut buffer, "abc");
+        buffer.push_char(
+            Character::new(Color::Default, Color::Default, '字' as u32),
+            true,
+        );
+
+        buffer.set_bounds((4, 3));
+        assert_eq!(screen(&buffer), ["abc", "字", ""]);
+
+        buffer.set_bounds((6, 3));
+        assert_eq!(screen(&buffer), ["abc字", "", ""]);
+    }
+
+    #[test]
+    fn reflow_joins_wide_characters_wrapped_when_printed() {
+        let mut buffer = buffer((4, 3));
+        print(&mut buffer, "abc");
+        buffer.push_char(
+            Character::new(Color::Default, Color::Default, '字' as u32),
+            true,
+        );
+        assert_eq!(screen(&buffer), ["abc", "字", ""]);
+
+        buffer.set_bounds((6, 3));
+        assert_eq!(screen(&buffer), ["abc字", "", ""]);
+    }
+
+    #[test]
+    fn shorter_keeps_cursor_line_and_scrolls_back_rows_above() {
+        let mut buffer = buffer((5, 4));
+        print(&mut buffer, "a\nb\nc\n$ ls");
+
+        buffer.set_bounds((5, 2));
+        assert_eq!(screen(&buffer), ["c", "$ ls"]);
+        assert_eq!(buffer.cursor_position, (4, 1));
+        assert_eq!(buffer.scrollback.len(), 2);
+
+        buffer.set_bounds((5, 4));
+        assert_eq!(screen(&buffer), ["a", "b", "c", "$ ls"]);
+        assert_eq!(buffer.cursor_position, (4, 3));
+        assert!(buffer.scrollback.is_empty());
+    }
+
+    #[test]
+    fn shorter_with_cursor_above_bottom_drops_blank_rows_below() {
+        let mut buffer = buffer((5, 4));
+        print(&mut buffer, "a\n$ ");
+
+        buffer.set_bounds((5, 2));
+        assert_eq!(screen(&buffer), ["a", "$"]);
+        assert_eq!(buffer.cursor_position, (2, 1));
+        assert!(buffer.scrollback.is_empty());
+    }
+
+    #[test]
+    fn combine_only_zero_width_chars() {
+        for (text, columns) in [("e\u{301}", 1), ("👨\u{200d}👩\u{200d}👧", 6), ("🇺🇸", 2)]
+        {
+            let mut buffer = buffer((10, 1));
+            for c in text.chars() {
+                if !buffer.combine(c) {
+                    buffer.push_char(
+                        Character::new(Color::Default, Color::Default, c as u32),
+                        c.width() == Some(2),
+                    );
                 }
             }
-            None => self.pending_change = Some(index..(index + 1)),
+            assert_eq!(buffer.cursor_position.0, columns, "{text}");
         }
     }
 
-    pub fn write_changes(&self, queue: &wgpu::Queue, buffer: &wgpu::Buffer) {
-        if let Some(range) = &self.pending_change {
-            queue.write_buffer(
-                buffer,
-                range.start as u64,
-                bytemuck::cast_slice(&self.characters[range.clone()]),
-            );
+    #[test]
+    fn cursor_movement_on_empty_grid() {
+        for bounds in [(10, 0), (0, 10), (0, 0)] {
+            let mut buffer = buffer(bounds);
+            for (x, y) in [(0, -1), (0, 1), (1, 0), (-1, 0), (3, 3)] {
+                buffer.move_cursor(x, y);
+            }
+            buffer.goto(5, 5);
+            buffer.goto_column(5);
+            buffer.goto_line(5);
+            print(&mut buffer, "abc\ndef");
+            buffer.erase_in_display(2);
+            buffer.erase_in_line(2);
+            buffer.set_bounds((10, 3));
+            buffer.move_cursor(0, -5);
+            assert_eq!(buffer.cursor_position.1, 0);
         }
     }
 
-    pub fn buffer(&self) -> &[Character] {
-        &self.characters
+    #[test]
+    fn resize_on_alternate_screen_reflows_primary() {
+        let mut buffer = buffer((10, 3));
+        print(&mut buffer, "abcdefghijkl");
+
+        buffer.set_alternate(true);
+        buffer.goto(0, 0);
+        print(&mut buffer, "ZZZZZZZZZZ\nZZZZZZZZZZ\nZZZZZZZZZZ");
+        buffer.set_bounds((5, 4));
+        assert_eq!(screen(&buffer), ["ZZZZZ", "ZZZZZ", "ZZZZZ", ""]);
+
+        buffer.set_alternate(false);
+        assert_eq!(screen(&buffer), ["abcde", "fghij", "kl", ""]);
+        assert_eq!(buffer.cursor_position, (2, 2));
     }
 
-    fn vec_coord(pos: (u32, u32)) -> usize {
-        (pos.1 * (globals::CHARACTER_BUFFER_WIDTH as u32) + pos.0) as usize
+    #[test]
+    fn resize_on_alternate_screen_restores_scrolled_back_primary_rows() {
+        let mut buffer = buffer((5, 4));
+        print(&mut buffer, "aaaaa\nbbbbb\nccccc\nddddd");
+        buffer.set_bounds((5, 2));
+
+        buffer.set_alternate(true);
+        buffer.set_bounds((5, 4));
+        buffer.set_alternate(false);
+        assert_eq!(screen(&buffer), ["aaaaa", "bbbbb", "ccccc", "ddddd"]);
     }
 }
@@ -0,0 +1,420 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{de::IntoDeserializer, Deserialize};
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+use crate::{
+    font::FontSettings,
+    globals,
+    palette::{rgb, Palette},
+};
+
+const DEFAULT_SCALE_FACTOR: f32 = 0.5;
+const DEFAULT_EFFECT: &str = "vhs";
+
+const DEFAULT_KEY_BINDINGS: [(VirtualKeyCode, ModifiersState, Action); 4] = [
+    (
+        VirtualKeyCode::F11,
+        ModifiersState::empty(),
+        Action::ToggleFullscreen,
+    ),
+    (
+        VirtualKeyCode::PageUp,
+        ModifiersState::SHIFT,
+        Action::ScrollPageUp,
+    ),
+    (
+        VirtualKeyCode::PageDown,
+        ModifiersState::SHIFT,
+        Action::ScrollPageDown,
+    ),
+    (
+        VirtualKeyCode::E,
+        ModifiersState::CTRL.union(ModifiersState::SHIFT),
+        Action::NextEffect,
+    ),
+];
+
+/// Settings read from `config.toml` in `$XDG_CONFIG_HOME/miniterm1999`, with defaults for the
+/// ones left out.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub shell: ShellConfig,
+    pub font: FontSettings,
+    pub colors: ColorConfig,
+    // name of the post-processing shader, see effect::directories
+    pub effect: String,
+    // lines kept once they scroll off the screen
+    pub scrollback: usize,
+    // size of the grid's pixels relative to the screen's
+    pub scale_factor: f32,
+    pub present_mode: PresentMode,
+    pub key_bindings: Vec<KeyBinding>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            shell: ShellConfig::default(),
+            font: FontSettings::default(),
+            colors: ColorConfig::default(),
+            effect: String::from(DEFAULT_EFFECT),
+            scrollback: globals::SCROLLBACK_SIZE,
+            scale_factor: DEFAULT_SCALE_FACTOR,
+            present_mode: PresentMode::Fifo,
+            key_bindings: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))
+            .ok_or(anyhow!("neither XDG_CONFIG_HOME nor HOME is set"))?;
+
+        Ok(config_home
+            .join(globals::CONFIG_DIRECTORY)
+            .join(globals::CONFIG_FILE))
+    }
+
+    /// Reads the config file, or returns the defaults if there is none.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))?;
+
+        config
+            .validate()
+            .with_context(|| format!("invalid config {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.scale_factor.is_nan() || self.scale_factor <= 0.0 {
+            bail!("scale_factor must be positive, not {}", self.scale_factor);
+        }
+
+        self.font.validate()?;
+
+        if self.colors.palette.len() > 256 {
+            bail!(
+                "colors.palette has {} colours, the palette only has 256",
+                self.colors.palette.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the action bound to `key` with exactly `modifiers` held, if any.
+    pub fn action(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Action> {
+        // the bindings from the config file take precedence over the default ones
+        self.key_bindings
+            .iter()
+            .map(|binding| (binding.key.key, binding.key.modifiers, binding.action))
+            .chain(DEFAULT_KEY_BINDINGS)
+            .find(|&(bound_key, bound_modifiers, _)| {
+                bound_key == key && bound_modifiers == modifiers
+            })
+            .map(|(_, _, action)| action)
+    }
+}
+
+/// Program started in the terminal.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShellConfig {
+    // $SHELL without one
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub cwd: Option<PathBuf>,
+}
+
+impl ShellConfig {
+    pub fn command(&self) -> Result<Command> {
+        let program = match &self.command {
+            Some(command) => command.clone(),
+            None => {
+                std::env::var("SHELL").context("no shell.command configured and SHELL unset")?
+            }
+        };
+
+        let mut command = Command::new(program);
+        command.args(&self.args).envs(&self.env);
+
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+
+        Ok(command)
+    }
+}
+
+/// Colours replacing the ones of the default palette.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorConfig {
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+    // the first colours of the 256 colour palette, starting with the 16 ANSI ones
+    pub palette: Vec<Rgb>,
+}
+
+impl ColorConfig {
+    pub fn palette(&self) -> Palette {
+        let mut palette = Palette::default();
+
+        for (color, &Rgb(rgb)) in palette.colors.iter_mut().zip(&self.palette) {
+            *color = rgb;
+        }
+        if let Some(Rgb(foreground)) = self.foreground {
+            palette.foreground = foreground;
+        }
+        if let Some(Rgb(background)) = self.background {
+            palette.background = background;
+        }
+
+        palette
+    }
+}
+
+/// Colour written as `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Rgb(pub [f32; 3]);
+
+impl TryFrom<String> for Rgb {
+    type Error = anyhow::Error;
+
+    fn try_from(string: String) -> Result<Self> {
+        let hex = string
+            .strip_prefix('#')
+            // from_str_radix would take a sign too
+            .filter(|hex| hex.len() == 6 && hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or(anyhow!("invalid colour {string:?}, expected #rrggbb"))?;
+
+        Ok(Self(rgb((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+    AutoVsync,
+    AutoNoVsync,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+            PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+            PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyBinding {
+    pub key: KeyCombination,
+    pub action: Action,
+}
+
+/// Key with the modifiers held, written like `Ctrl+Shift+PageUp`, with the key named as in
+/// winit's `VirtualKeyCode`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyCombination {
+    pub key: VirtualKeyCode,
+    pub modifiers: ModifiersState,
+}
+
+impl TryFrom<String> for KeyCombination {
+    type Error = anyhow::Error;
+
+    fn try_from(string: String) -> Result<Self> {
+        let mut names: Vec<&str> = string.split('+').map(str::trim).collect();
+        let key_name = names.pop().unwrap_or_default();
+        let key = VirtualKeyCode::deserialize(key_name.into_deserializer()).map_err(
+            |_: serde::de::value::Error| anyhow!("unknown key {key_name:?} in {string:?}"),
+        )?;
+
+        let mut modifiers = ModifiersState::empty();
+        for name in names {
+            modifiers |= match name.to_lowercase().as_str() {
+                "shift" => ModifiersState::SHIFT,
+                "ctrl" | "control" => ModifiersState::CTRL,
+                "alt" => ModifiersState::ALT,
+                "super" | "logo" => ModifiersState::LOGO,
+                _ => bail!("unknown modifier {name:?} in {string:?}"),
+            };
+        }
+
+        Ok(Self { key, modifiers })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    ToggleFullscreen,
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollToBottom,
+    NextEffect,
+}
+
+/// Notices changes to the config file by polling its modification time.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            modified: Self::modified(&path),
+            path,
+            checked_at: Instant::now(),
+        }
+    }
+
+    /// Returns the config read again if the file changed since the last call, checking it at
+    /// most every CONFIG_POLL_INTERVAL.
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        if self.checked_at.elapsed() < globals::CONFIG_POLL_INTERVAL {
+            return None;
+        }
+        self.checked_at = Instant::now();
+
+        let modified = Self::modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        Some(Config::load(&self.path))
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).ok()?.modified().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        assert_eq!(
+            Rgb::try_from(String::from("#ff8000")).unwrap(),
+            Rgb(rgb(255, 128, 0))
+        );
+
+        for invalid in [
+            "", "#", "ff8000", "#ff800", "#ff80000", "#gg8000", "#+f8000",
+        ] {
+            assert!(Rgb::try_from(String::from(invalid)).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn key_combinations() {
+        let combination = KeyCombination::try_from(String::from("Ctrl + shift+PageUp")).unwrap();
+        assert_eq!(combination.key, VirtualKeyCode::PageUp);
+        assert_eq!(
+            combination.modifiers,
+            ModifiersState::CTRL | ModifiersState::SHIFT
+        );
+
+        let combination = KeyCombination::try_from(String::from("F11")).unwrap();
+        assert_eq!(combination.key, VirtualKeyCode::F11);
+        assert!(combination.modifiers.is_empty());
+
+        for invalid in ["", "Ctrl+", "Ctrl+Pageup", "Hyper+A", "Ctrl+Shift", "A+B"] {
+            assert!(
+                KeyCombination::try_from(String::from(invalid)).is_err(),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn config_file() {
+        let config: Config = toml::from_str(
+            r##"
+            effect = "none"
+            [colors]
+            background = "#101010"
+            palette = ["#000000", "#aa0000"]
+            [[key_bindings]]
+            key = "Ctrl+Shift+E"
+            action = "scroll_to_bottom"
+            "##,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let palette = config.colors.palette();
+        assert_eq!(palette.background, rgb(16, 16, 16));
+        assert_eq!(palette.colors[1], rgb(170, 0, 0));
+        assert_eq!(
+            config.action(
+                VirtualKeyCode::E,
+                ModifiersState::CTRL | ModifiersState::SHIFT
+            ),
+            Some(Action::ScrollToBottom)
+        );
+        assert_eq!(
+            config.action(VirtualKeyCode::F11, ModifiersState::empty()),
+            Some(Action::ToggleFullscreen)
+        );
+
+        assert!(toml::from_str::<Config>("[colors]\nforeground = \"white\"").is_err());
+        assert!(toml::from_str::<Config>(
+            "[[key_bindings]]\nkey = \"Ctrl+Nope\"\naction = \"next_effect\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        for invalid in [
+            "scale_factor = 0",
+            "font.size = 0",
+            "font.size = nan",
+            "font.size = 300",
+            "font.line_spacing = 400",
+            "font.letter_spacing = -400",
+        ] {
+            let config: Config = toml::from_str(invalid).unwrap();
+            assert!(config.validate().is_err(), "{invalid:?}");
+        }
+    }
+}
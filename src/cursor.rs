@@ -0,0 +1,29 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block = 0,
+    Underline = 1,
+    Bar = 2,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Cursor {
+    pub position: [u32; 2],
+    pub shape: u32,
+    pub flags: u32,
+}
+
+impl Cursor {
+    // Must match the ones in shader_base.wgsl
+    pub const VISIBLE: u32 = 1 << 0;
+    pub const BLINKING: u32 = 1 << 1;
+    pub const FOCUSED: u32 = 1 << 2;
+
+    pub fn new(position: (u32, u32), shape: CursorShape, flags: u32) -> Self {
+        Self {
+            position: [position.0, position.1],
+            shape: shape as u32,
+            flags,
+        }
+    }
+}
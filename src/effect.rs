@@ -0,0 +1,528 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use naga::{
+    valid::{Capabilities, ValidationFlags, Validator},
+    Span,
+};
+use serde::Deserialize;
+
+use crate::{config::Config, globals};
+
+// the passthrough one is always there, even with a user file of that name
+pub const NONE: &str = "none";
+
+const BUNDLED: [(&str, &str); 2] = [
+    (NONE, include_str!("../shaders/none")),
+    ("vhs", include_str!("../shaders/vhs")),
+];
+
+const TEMPLATE: &str = include_str!("shader_final.pre-wgsl");
+const TEMPLATE_NAME: &str = "shader_final.pre-wgsl";
+const MARKER: &str = "//#FXDEF";
+const INPUTS_MARKER: &str = "//#INPUTSDEF";
+const MANIFEST_EXTENSION: &str = "toml";
+// each pass reads the outputs of the previous ones, which have to fit in the 16 textures a
+// shader can sample
+const MAX_PASSES: usize = 8;
+
+/// Post-processing shader, either a single WGSL file defining `fx()`, or a manifest listing
+/// passes which each define one.
+pub struct Effect {
+    pub name: String,
+    pub passes: Vec<Pass>,
+    // whether the passes can read the previous frame
+    pub feedback: bool,
+    // file named after the effect, none for the bundled ones
+    pub path: Option<PathBuf>,
+}
+
+/// Pass of an effect, drawing into a texture of its own read by the passes after it. The last
+/// pass draws to the screen.
+pub struct Pass {
+    pub source: String,
+    pub path: Option<PathBuf>,
+    // size of the output relative to the screen's, and its format, unused for the last pass
+    pub scale: f32,
+    pub format: PassFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassFormat {
+    #[default]
+    Rgba8Unorm,
+    Rgba16Float,
+}
+
+impl From<PassFormat> for wgpu::TextureFormat {
+    fn from(format: PassFormat) -> Self {
+        match format {
+            PassFormat::Rgba8Unorm => wgpu::TextureFormat::Rgba8Unorm,
+            PassFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    feedback: bool,
+    #[serde(rename = "pass")]
+    passes: Vec<PassManifest>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PassManifest {
+    // relative to the manifest
+    shader: PathBuf,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default)]
+    format: PassFormat,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+impl Effect {
+    /// Parses and validates the final shader of each pass with naga, describing the errors at
+    /// their lines in the pass's file rather than in the final shader.
+    pub fn check(&self) -> Result<()> {
+        for (index, pass) in self.passes.iter().enumerate() {
+            pass.check(index)
+                .with_context(|| format!("effect {}", self.name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the files the effect was read from.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.path
+            .iter()
+            .chain(self.passes.iter().filter_map(|pass| pass.path.as_ref()))
+            .map(PathBuf::as_path)
+    }
+}
+
+impl Pass {
+    /// Returns the final shader of the pass at `index`, the template with the pass in place of
+    /// its marker, and the textures of the previous passes in place of the inputs marker.
+    pub fn shader(&self, index: usize) -> String {
+        let inputs = Self::inputs(index);
+
+        self.pieces(&inputs)
+            .iter()
+            .map(|(_, text, range)| &text[range.clone()])
+            .collect()
+    }
+
+    fn check(&self, index: usize) -> Result<()> {
+        let inputs = Self::inputs(index);
+        let shader = self.shader(index);
+
+        let module = naga::front::wgsl::parse_str(&shader)
+            .map_err(|error| anyhow!(self.describe(&inputs, error.message(), error.labels())))?;
+
+        Validator::new(ValidationFlags::all(), Capabilities::empty())
+            .validate(&module)
+            .map_err(|error| {
+                let mut message = error.as_inner().to_string();
+                let mut source = std::error::Error::source(error.as_inner());
+                while let Some(error) = source {
+                    message = format!("{message}: {error}");
+                    source = error.source();
+                }
+
+                anyhow!(self.describe(
+                    &inputs,
+                    &message,
+                    error.spans().map(|(span, label)| (*span, label.as_str()))
+                ))
+            })?;
+
+        Ok(())
+    }
+
+    // Declares the textures of the previous passes, with a `passN_color()` to sample each and a
+    // `previous_color()` for the one just before, the screen for the first pass
+    fn inputs(index: usize) -> String {
+        let mut inputs = String::new();
+
+        for input in 0..index {
+            inputs += &format!(
+                "@group(2) @binding({})\nvar t_pass{input}: texture_2d<f32>;\n\
+                 fn pass{input}_color(coords: vec2<f32>) -> vec4<f32> {{\n\
+                 \treturn textureSample(t_pass{input}, s_pass, coords);\n}}\n",
+                input + 2
+            );
+        }
+
+        let previous = match index {
+            0 => String::from("screen_color"),
+            index => format!("pass{}_color", index - 1),
+        };
+        inputs += &format!(
+            "fn previous_color(coords: vec2<f32>) -> vec4<f32> {{\n\treturn {previous}(coords);\n}}\n"
+        );
+
+        inputs
+    }
+
+    // Pieces the final shader is made of, each with the name of the file it comes from, its text
+    // and its range in the text
+    fn pieces<'a>(&'a self, inputs: &'a str) -> [(&'a str, &'a str, Range<usize>); 5] {
+        let marker = TEMPLATE.find(MARKER).unwrap_or_default();
+        let inputs_marker = TEMPLATE.find(INPUTS_MARKER).unwrap_or(TEMPLATE.len());
+
+        [
+            (TEMPLATE_NAME, TEMPLATE, 0..marker),
+            (self.file_name(), &self.source, 0..self.source.len()),
+            (
+                TEMPLATE_NAME,
+                TEMPLATE,
+                marker + MARKER.len()..inputs_marker,
+            ),
+            ("pass inputs", inputs, 0..inputs.len()),
+            (
+                TEMPLATE_NAME,
+                TEMPLATE,
+                (inputs_marker + INPUTS_MARKER.len()).min(TEMPLATE.len())..TEMPLATE.len(),
+            ),
+        ]
+    }
+
+    fn file_name(&self) -> &str {
+        self.path
+            .as_deref()
+            .and_then(Path::to_str)
+            .unwrap_or("bundled effect")
+    }
+
+    fn describe<'a>(
+        &self,
+        inputs: &str,
+        message: &str,
+        labels: impl Iterator<Item = (Span, &'a str)>,
+    ) -> String {
+        let pieces = self.pieces(inputs);
+        let mut description = String::from(message);
+
+        for (span, label) in labels {
+            let Some(range) = span.to_range() else {
+                continue;
+            };
+
+            // the spans are in the final shader, mapped back to the piece they fall in
+            let mut offset = 0;
+            let Some((file_name, text, start)) =
+                pieces.iter().find_map(|(file_name, text, piece)| {
+                    let start = range.start - offset;
+                    offset += piece.len();
+                    (start < piece.len()).then_some((file_name, text, piece.start + start))
+                })
+            else {
+                continue;
+            };
+            let location = Span::from(start..start).location(text);
+
+            let line_start = text[..start].rfind('\n').map_or(0, |index| index + 1);
+            let line_end = text[start..]
+                .find('\n')
+                .map_or(text.len(), |index| start + index);
+            let line = text[line_start..line_end].replace('\t', "    ");
+            let column = text[line_start..start]
+                .replace('\t', "    ")
+                .chars()
+                .count();
+            let length = range.len().clamp(1, (line_end - start).max(1));
+
+            description += &format!(
+                "\n{file_name}:{}:{}: {label}\n    {line}\n    {}{}",
+                location.line_number,
+                location.line_position,
+                " ".repeat(column),
+                "^".repeat(length),
+            );
+        }
+
+        description
+    }
+}
+
+/// Directories searched for effect files, the user's before the system ones. An effect is
+/// named after its file, without the extension.
+pub fn directories() -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+
+    if let Some(config_directory) = Config::path().ok().and_then(|path| {
+        path.parent()
+            .map(|directory| directory.join(globals::EFFECT_DIRECTORY))
+    }) {
+        directories.push(config_directory);
+    }
+
+    let data_directories = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|directories| !directories.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+    directories.extend(
+        data_directories
+            .split(':')
+            .filter(|directory| !directory.is_empty())
+            .map(|directory| {
+                PathBuf::from(directory)
+                    .join(globals::CONFIG_DIRECTORY)
+                    .join(globals::EFFECT_DIRECTORY)
+            }),
+    );
+
+    directories
+}
+
+/// Names of the available effects, sorted.
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = BUNDLED.iter().map(|(name, _)| name.to_string()).collect();
+
+    for directory in directories() {
+        let Ok(entries) = std::fs::read_dir(&directory) else {
+            continue;
+        };
+        names.extend(
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+                .filter(|name| !name.starts_with('.')),
+        );
+    }
+
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Returns the effect after `name`, wrapping around to the first.
+pub fn next(name: &str) -> String {
+    let names = names();
+    let index = names
+        .iter()
+        .position(|other| other == name)
+        .map_or(0, |index| (index + 1) % names.len());
+
+    names[index].clone()
+}
+
+/// Loads the effect from the first directory that has a file named after it, falling back to
+/// the bundled ones. A file with the `toml` extension is a manifest listing its passes.
+pub fn load(name: &str) -> Result<Effect> {
+    let path = path(name);
+
+    let (passes, feedback) = match &path {
+        Some(path)
+            if path
+                .extension()
+                .is_some_and(|extension| extension == MANIFEST_EXTENSION) =>
+        {
+            load_manifest(path)?
+        }
+        Some(path) => (
+            vec![Pass {
+                source: std::fs::read_to_string(path)
+                    .with_context(|| format!("reading effect {}", path.display()))?,
+                path: Some(path.clone()),
+                scale: 1.0,
+                format: PassFormat::default(),
+            }],
+            false,
+        ),
+        None => (
+            vec![Pass {
+                source: BUNDLED
+                    .iter()
+                    .find(|(bundled_name, _)| *bundled_name == name)
+                    .map(|(_, source)| source.to_string())
+                    .ok_or(anyhow!("no effect named {name:?}"))?,
+                path: None,
+                scale: 1.0,
+                format: PassFormat::default(),
+            }],
+            false,
+        ),
+    };
+
+    Ok(Effect {
+        name: name.to_owned(),
+        passes,
+        feedback,
+        path,
+    })
+}
+
+// Reads the passes listed in a manifest, and whether they can read the previous frame
+fn load_manifest(path: &Path) -> Result<(Vec<Pass>, bool)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading effect {}", path.display()))?;
+    let manifest: Manifest =
+        toml::from_str(&text).with_context(|| format!("invalid effect {}", path.display()))?;
+
+    if manifest.passes.is_empty() || manifest.passes.len() > MAX_PASSES {
+        bail!(
+            "invalid effect {}: it has {} passes, it can have 1 to {MAX_PASSES}",
+            path.display(),
+            manifest.passes.len()
+        );
+    }
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let passes = manifest
+        .passes
+        .into_iter()
+        .map(|pass| {
+            if pass.scale.is_nan() || pass.scale <= 0.0 {
+                bail!(
+                    "invalid effect {}: pass scale must be positive, not {}",
+                    path.display(),
+                    pass.scale
+                );
+            }
+
+            let shader = directory.join(pass.shader);
+            Ok(Pass {
+                source: std::fs::read_to_string(&shader)
+                    .with_context(|| format!("reading effect pass {}", shader.display()))?,
+                path: Some(shader),
+                scale: pass.scale,
+                format: pass.format,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok((passes, manifest.feedback))
+}
+
+// File the effect is read from, none for the bundled ones that aren't overridden
+fn path(name: &str) -> Option<PathBuf> {
+    directories()
+        .iter()
+        .filter(|_| name != NONE)
+        .find_map(|directory| find(directory, name))
+}
+
+fn find(directory: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(directory)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_file() && path.file_stem().and_then(|stem| stem.to_str()) == Some(name)
+        })
+}
+
+/// Loads an effect in the background, and again whenever one of its files changes, checking
+/// their modification times at most every EFFECT_POLL_INTERVAL.
+pub struct EffectWatcher {
+    name: String,
+    path: Option<PathBuf>,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    checked_at: Instant,
+    loading: Option<Receiver<Loaded>>,
+}
+
+// effect loaded and checked in the background, with the files it was read from
+type Loaded = (Vec<(PathBuf, Option<SystemTime>)>, Result<Effect>);
+
+impl EffectWatcher {
+    pub fn new(name: &str) -> Self {
+        let mut watcher = Self {
+            name: name.to_owned(),
+            path: None,
+            files: Vec::new(),
+            checked_at: Instant::now(),
+            loading: None,
+        };
+        watcher.reload();
+
+        watcher
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the effect once it's loaded and checked, after creating the watcher or after one
+    /// of its files changed.
+    pub fn poll(&mut self) -> Option<Result<Effect>> {
+        if let Some(loading) = &self.loading {
+            match loading.try_recv() {
+                Ok((files, result)) => {
+                    self.files = files;
+                    self.loading = None;
+                    return Some(result);
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => self.loading = None,
+            }
+        }
+
+        if self.checked_at.elapsed() < globals::EFFECT_POLL_INTERVAL {
+            return None;
+        }
+        self.checked_at = Instant::now();
+
+        // a file named after the effect may also have appeared in a directory searched first
+        if path(&self.name) != self.path
+            || self
+                .files
+                .iter()
+                .any(|(path, modified)| Self::modified(path) != *modified)
+        {
+            self.reload();
+        }
+
+        None
+    }
+
+    fn reload(&mut self) {
+        self.path = path(&self.name);
+
+        let (sender, receiver) = mpsc::channel();
+        let name = self.name.clone();
+        let path = self.path.clone();
+        std::thread::spawn(move || {
+            let result = load(&name);
+            let files: Vec<PathBuf> = match &result {
+                Ok(effect) => effect.files().map(Path::to_path_buf).collect(),
+                Err(_) => path.into_iter().collect(),
+            };
+            let files = files
+                .into_iter()
+                .map(|path| {
+                    let modified = Self::modified(&path);
+                    (path, modified)
+                })
+                .collect();
+
+            let result = result.and_then(|effect| {
+                effect.check()?;
+                Ok(effect)
+            });
+            sender.send((files, result)).ok();
+        });
+        self.loading = Some(receiver);
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).ok()?.modified().ok()
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    future::Future,
+    task::{Context, Poll, Waker},
+};
+
+use anyhow::{anyhow, Result};
+use wgpu::*;
+use winit::dpi::PhysicalSize;
+
+use crate::{effect::Effect, vertex::Vertex};
+
+// float so that effects fading the previous frame out don't get stuck on rounding
+const FEEDBACK_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Passes of an effect on the GPU. Each pass but the last draws into a texture of its own,
+/// sized relative to the screen, which the passes after it can sample. The last one draws to
+/// the screen, and for effects with feedback also into the texture the next frame reads it
+/// from.
+pub struct EffectChain {
+    sampler: Sampler,
+    passes: Vec<ChainPass>,
+    // outputs of all the passes but the last
+    outputs: Vec<(Texture, TextureView)>,
+    feedback: bool,
+    // the two textures the frames are drawn into in turn with feedback, a blank one without
+    frames: Vec<(Texture, TextureView)>,
+    // sampler, previous frame and outputs of the previous passes, for each pass and each
+    // feedback texture
+    inputs: Vec<Vec<BindGroup>>,
+    frame: usize,
+}
+
+struct ChainPass {
+    pipeline: RenderPipeline,
+    inputs_layout: BindGroupLayout,
+    scale: f32,
+    format: TextureFormat,
+}
+
+impl EffectChain {
+    /// Builds the pipelines of the passes of `effect`, with `bind_group_layouts` for the screen
+    /// texture and the shader parameters. Returns the errors wgpu reports instead of panicking
+    /// on them. This can take a while, so it's meant to run off the event loop, and the chain
+    /// needs a `resize` before rendering.
+    pub fn new(
+        device: &Device,
+        effect: &Effect,
+        bind_group_layouts: [&BindGroupLayout; 2],
+        surface_format: TextureFormat,
+    ) -> Result<Self> {
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        device.push_error_scope(ErrorFilter::Validation);
+
+        let last = effect.passes.len() - 1;
+        let pipelines: Vec<(RenderPipeline, BindGroupLayout)> = effect
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| {
+                let (entry_point, formats) = match index {
+                    index if index == last && effect.feedback => {
+                        ("fs_feedback", vec![surface_format, FEEDBACK_FORMAT])
+                    }
+                    index if index == last => ("fs_main", vec![surface_format]),
+                    _ => ("fs_main", vec![pass.format.into()]),
+                };
+                Self::create_pipeline(
+                    device,
+                    bind_group_layouts,
+                    index,
+                    &pass.shader(index),
+                    entry_point,
+                    &formats,
+                )
+            })
+            .collect();
+
+        // the native backends report errors right away, so the scope is ready once popped. The
+        // scope is shared with the event loop thread, which doesn't push any of its own
+        let error =
+            std::pin::pin!(device.pop_error_scope()).poll(&mut Context::from_waker(Waker::noop()));
+        if let Poll::Ready(Some(error)) = error {
+            return Err(anyhow!("effect {}: {error}", effect.name));
+        }
+
+        let passes = pipelines
+            .into_iter()
+            .zip(&effect.passes)
+            .map(|((pipeline, inputs_layout), pass)| ChainPass {
+                pipeline,
+                inputs_layout,
+                scale: pass.scale,
+                format: pass.format.into(),
+            })
+            .collect();
+
+        Ok(Self {
+            sampler,
+            passes,
+            outputs: Vec::new(),
+            feedback: effect.feedback,
+            frames: Vec::new(),
+            inputs: Vec::new(),
+            frame: 0,
+        })
+    }
+
+    /// Recreates the outputs of the passes for a screen of `size`, the previous frame starting
+    /// out blank.
+    pub fn resize(&mut self, device: &Device, size: PhysicalSize<u32>) {
+        let scaled_size = |scale: f32| {
+            let max_size = device.limits().max_texture_dimension_2d;
+            (
+                ((size.width as f32 * scale).round() as u32).clamp(1, max_size),
+                ((size.height as f32 * scale).round() as u32).clamp(1, max_size),
+            )
+        };
+
+        for (texture, _) in self.outputs.drain(..).chain(self.frames.drain(..)) {
+            texture.destroy();
+        }
+
+        self.outputs = self.passes[..self.passes.len() - 1]
+            .iter()
+            .map(|pass| {
+                Self::create_texture(
+                    device,
+                    "Effect Pass Texture",
+                    scaled_size(pass.scale),
+                    pass.format,
+                )
+            })
+            .collect();
+
+        self.frames = match self.feedback {
+            true => (0..2)
+                .map(|_| {
+                    Self::create_texture(
+                        device,
+                        "Effect Feedback Texture",
+                        scaled_size(1.0),
+                        FEEDBACK_FORMAT,
+                    )
+                })
+                .collect(),
+            false => vec![Self::create_texture(
+                device,
+                "Effect Feedback Texture",
+                (1, 1),
+                FEEDBACK_FORMAT,
+            )],
+        };
+
+        self.inputs = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| {
+                self.frames
+                    .iter()
+                    .map(|(_, previous_frame)| {
+                        let entries: Vec<BindGroupEntry> = [
+                            BindGroupEntry {
+                                binding: 0,
+                                resource: BindingResource::Sampler(&self.sampler),
+                            },
+                            BindGroupEntry {
+                                binding: 1,
+                                resource: BindingResource::TextureView(previous_frame),
+                            },
+                        ]
+                        .into_iter()
+                        .chain(self.outputs[..index].iter().enumerate().map(
+                            |(input, (_, view))| BindGroupEntry {
+                                binding: input as u32 + 2,
+                                resource: BindingResource::TextureView(view),
+                            },
+                        ))
+                        .collect();
+
+                        device.create_bind_group(&BindGroupDescriptor {
+                            label: Some("Effect Pass Inputs Bind Group"),
+                            layout: &pass.inputs_layout,
+                            entries: &entries,
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    fn create_texture(
+        device: &Device,
+        label: &str,
+        (width, height): (u32, u32),
+        format: TextureFormat,
+    ) -> (Texture, TextureView) {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+
+    /// Runs the passes, the last one drawing into `view`. `bind_groups` are the screen texture
+    /// and the shader parameters.
+    pub fn render(
+        &mut self,
+        commands: &mut CommandEncoder,
+        view: &TextureView,
+        bind_groups: [&BindGroup; 2],
+        vertex_buffer: &Buffer,
+    ) {
+        // with feedback, the frame is drawn into the texture the previous one isn't in
+        let previous_frame = self.frame % self.frames.len();
+        let feedback = self.feedback.then(|| &self.frames[1 - previous_frame].1);
+        self.frame += 1;
+
+        for (index, (pass, inputs)) in self.passes.iter().zip(&self.inputs).enumerate() {
+            let targets: Vec<&TextureView> = match self.outputs.get(index) {
+                Some((_, output)) => vec![output],
+                None => std::iter::once(view).chain(feedback).collect(),
+            };
+            let color_attachments: Vec<Option<RenderPassColorAttachment>> = targets
+                .into_iter()
+                .map(|view| {
+                    Some(RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color::BLACK),
+                            store: true,
+                        },
+                    })
+                })
+                .collect();
+
+            let mut render_pass = commands.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Effect Render Pass"),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.set_bind_group(0, bind_groups[0], &[]);
+            render_pass.set_bind_group(1, bind_groups[1], &[]);
+            render_pass.set_bind_group(2, &inputs[previous_frame], &[]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        bind_group_layouts: [&BindGroupLayout; 2],
+        index: usize,
+        shader: &str,
+        entry_point: &str,
+        formats: &[TextureFormat],
+    ) -> (RenderPipeline, BindGroupLayout) {
+        // the sampler, then the previous frame and the outputs of the previous passes
+        let entries: Vec<BindGroupLayoutEntry> = std::iter::once(BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        })
+        .chain((0..index + 1).map(|input| BindGroupLayoutEntry {
+            binding: input as u32 + 1,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: TextureViewDimension::D2,
+                sample_type: TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        }))
+        .collect();
+
+        let inputs_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Effect Pass Inputs Bind Group Layout"),
+            entries: &entries,
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Effect Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layouts[0], bind_group_layouts[1], &inputs_layout],
+            push_constant_ranges: &[],
+        });
+
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Shader Final"),
+            source: ShaderSource::Wgsl(shader.into()),
+        });
+
+        let targets: Vec<Option<ColorTargetState>> = formats
+            .iter()
+            .map(|&format| {
+                Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })
+            })
+            .collect();
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Final Render Pipeline"),
+            layout: Some(&layout),
+            vertex: VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point,
+                targets: &targets,
+            }),
+            multiview: None,
+        });
+
+        (pipeline, inputs_layout)
+    }
+}
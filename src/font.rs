@@ -0,0 +1,317 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{anyhow, bail, Context, Result};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{bitmap_font::BitmapFont, box_drawing, globals, truetype_font::TrueTypeFont};
+
+const DEFAULT_FONT_SIZE: f32 = 10.0;
+// limits keeping the glyphs a reasonable size for the glyph atlas
+const MAX_FONT_SIZE: f32 = 200.0;
+const MAX_SPACING: i32 = 100;
+const DEFAULT_FALLBACK_FONTS: &[&str] = &["Symbols Nerd Font Mono", "Noto Sans Symbols 2", "emoji"];
+
+/// Font chosen by the user, and the spacing added between its glyphs, in pixels.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FontSettings {
+    // path or fontconfig pattern, the bundled font is used without one
+    pub name: Option<String>,
+    // consulted in order for glyphs missing from the font, or from the bundled one without a name
+    pub fallbacks: Vec<String>,
+    pub size: f32,
+    pub line_spacing: i32,
+    pub letter_spacing: i32,
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        Self {
+            name: None,
+            fallbacks: DEFAULT_FALLBACK_FONTS
+                .iter()
+                .map(|&font| font.to_owned())
+                .collect(),
+            size: DEFAULT_FONT_SIZE,
+            line_spacing: 0,
+            letter_spacing: 0,
+        }
+    }
+}
+
+impl FontSettings {
+    /// Overrides the settings with the ones set in environment variables.
+    pub fn with_env(self) -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok()?.parse().ok()
+        }
+
+        Self {
+            name: var(globals::FONT_VARIABLE).or(self.name),
+            fallbacks: var::<String>(globals::FALLBACK_FONTS_VARIABLE)
+                .map(|fonts| {
+                    fonts
+                        .split(',')
+                        .map(|font| font.trim().to_owned())
+                        .collect()
+                })
+                .unwrap_or(self.fallbacks),
+            size: var(globals::FONT_SIZE_VARIABLE).unwrap_or(self.size),
+            line_spacing: var(globals::LINE_SPACING_VARIABLE).unwrap_or(self.line_spacing),
+            letter_spacing: var(globals::LETTER_SPACING_VARIABLE).unwrap_or(self.letter_spacing),
+        }
+    }
+
+    /// Fails if the size or the spacing is out of bounds.
+    pub fn validate(&self) -> Result<()> {
+        if !(self.size > 0.0 && self.size <= MAX_FONT_SIZE) {
+            bail!(
+                "font size must be positive and at most {MAX_FONT_SIZE}, not {}",
+                self.size
+            );
+        }
+
+        for (name, spacing) in [
+            ("line_spacing", self.line_spacing),
+            ("letter_spacing", self.letter_spacing),
+        ] {
+            if !(-MAX_SPACING..=MAX_SPACING).contains(&spacing) {
+                bail!(
+                    "font {name} must be between -{MAX_SPACING} and {MAX_SPACING}, not {spacing}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Image a glyph is drawn into, in premultiplied RGBA. It's two cells wide, so it can hold wide
+/// characters.
+pub struct Tile {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    // whether the glyph has its own colours, rather than coverage drawn in the foreground colour
+    pub colored: bool,
+    // cell of the font, placed in the middle of the spacing
+    pub glyph_origin: (i32, i32),
+    pub glyph_size: (u32, u32),
+}
+
+impl Tile {
+    pub fn new(glyph_size: (u32, u32), spacing: (i32, i32)) -> Self {
+        let cell_width = (glyph_size.0 as i32 + spacing.0).max(1) as u32;
+        let (width, height) = (
+            cell_width * 2,
+            (glyph_size.1 as i32 + spacing.1).max(1) as u32,
+        );
+
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            colored: false,
+            glyph_origin: (spacing.0 / 2, spacing.1 / 2),
+            glyph_size,
+        }
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        (self.width / 2, self.height)
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+        self.colored = false;
+    }
+
+    // Draws a coverage image with its top left corner at `x`, `y` from the glyph origin, clipped
+    // to the tile
+    pub fn blit(&mut self, x: i32, y: i32, width: u32, height: u32, coverage: &[u8]) {
+        self.blend(x, y, width, height, |pixel, index| {
+            pixel
+                .iter_mut()
+                .for_each(|channel| *channel = (*channel).max(coverage[index]));
+        });
+    }
+
+    // Draws an image in straight alpha RGBA over the tile, like `blit`
+    pub fn blit_color(&mut self, x: i32, y: i32, width: u32, height: u32, rgba: &[u8]) {
+        self.colored = true;
+        self.blend(x, y, width, height, |pixel, index| {
+            let source = &rgba[index * 4..index * 4 + 4];
+            let alpha = source[3] as u32;
+
+            for channel in 0..4 {
+                let color = if channel == 3 {
+                    255
+                } else {
+                    source[channel] as u32
+                };
+                pixel[channel] =
+                    ((color * alpha + pixel[channel] as u32 * (255 - alpha)) / 255) as u8;
+            }
+        });
+    }
+
+    fn blend(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        mut blend: impl FnMut(&mut [u8], usize),
+    ) {
+        let (x, y) = (x + self.glyph_origin.0, y + self.glyph_origin.1);
+
+        for row in 0..height as i32 {
+            for column in 0..width as i32 {
+                let (tile_x, tile_y) = (x + column, y + row);
+
+                if tile_x < 0
+                    || tile_y < 0
+                    || tile_x >= self.width as i32
+                    || tile_y >= self.height as i32
+                {
+                    continue;
+                }
+
+                let pixel = ((tile_y as u32 * self.width + tile_x as u32) * 4) as usize;
+                blend(
+                    &mut self.pixels[pixel..pixel + 4],
+                    (row * width as i32 + column) as usize,
+                );
+            }
+        }
+    }
+}
+
+pub trait GlyphSource {
+    /// Size of the cells of the font: its advance width, and its ascent, descent and line gap.
+    fn cell_size(&self) -> (u32, u32);
+
+    /// Draws `cluster` into `tile`. Returns false if the font has no glyph for it.
+    fn draw(&mut self, cluster: &str, tile: &mut Tile) -> bool;
+}
+
+/// Fonts consulted in order for each glyph, with a box drawn for glyphs none of them have. Box
+/// drawing and similar characters are drawn without them.
+pub struct FontChain {
+    fonts: Vec<Box<dyn GlyphSource>>,
+}
+
+impl GlyphSource for FontChain {
+    // the cells of the first font
+    fn cell_size(&self) -> (u32, u32) {
+        self.fonts[0].cell_size()
+    }
+
+    fn draw(&mut self, cluster: &str, tile: &mut Tile) -> bool {
+        if box_drawing::draw(cluster, tile)
+            || self.fonts.iter_mut().any(|font| font.draw(cluster, tile))
+        {
+            return true;
+        }
+
+        // outline of the cell, inset by a pixel
+        let cells = cluster.width().clamp(1, 2) as u32;
+        let (width, height) = (
+            (tile.glyph_size.0 * cells).max(3) - 2,
+            tile.glyph_size.1.max(3) - 2,
+        );
+        let coverage: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        tile.blit(1, 1, width, height, &coverage);
+        true
+    }
+}
+
+/// Loads the font named in `settings` followed by its fallbacks, each either a path or a
+/// fontconfig pattern like `monospace:bold`. PSF, BDF and PCF bitmap fonts are recognised by
+/// their contents. The bundled bitmap font comes last, or first in place of the named font
+/// without a font name.
+pub fn load(settings: &FontSettings) -> Result<FontChain> {
+    settings.validate()?;
+
+    let mut fonts: Vec<Box<dyn GlyphSource>> = Vec::new();
+    let mut paths = Vec::new();
+
+    match settings.name.as_deref() {
+        Some(spec) => {
+            let path = resolve(spec)?;
+            fonts.push(load_file(&path, settings.size)?);
+            paths.push(path);
+        }
+        // ahead of the fallbacks, as fontconfig may substitute a font with letters for them
+        None => fonts.push(Box::new(BitmapFont::bundled()?)),
+    }
+
+    for spec in &settings.fallbacks {
+        // fontconfig substitutes a font for patterns it has no match for
+        match resolve(spec) {
+            Ok(path) if paths.contains(&path) => {}
+            Ok(path) => match load_file(&path, settings.size) {
+                Ok(font) => {
+                    fonts.push(font);
+                    paths.push(path);
+                }
+                Err(error) => tracing::warn!("skipping fallback font: {error:#}"),
+            },
+            Err(error) => tracing::warn!("skipping fallback font: {error:#}"),
+        }
+    }
+
+    if settings.name.is_some() {
+        fonts.push(Box::new(BitmapFont::bundled()?));
+    }
+
+    Ok(FontChain { fonts })
+}
+
+fn resolve(spec: &str) -> Result<String> {
+    if Path::new(spec).exists() {
+        Ok(spec.to_owned())
+    } else {
+        fontconfig_match(spec)
+    }
+}
+
+fn load_file(path: &str, size: f32) -> Result<Box<dyn GlyphSource>> {
+    let data = std::fs::read(path).with_context(|| format!("reading font {path}"))?;
+
+    if BitmapFont::detect(&data) {
+        return Ok(Box::new(
+            BitmapFont::load(&data).with_context(|| format!("loading font {path}"))?,
+        ));
+    }
+
+    Ok(Box::new(
+        TrueTypeFont::new(data, size).with_context(|| format!("loading font {path}"))?,
+    ))
+}
+
+// Asks fontconfig for the file of the best font matching `pattern`
+fn fontconfig_match(pattern: &str) -> Result<String> {
+    let output = Command::new("fc-match")
+        .args(["--format=%{file}", pattern])
+        .output()
+        .context("running fc-match")?;
+
+    let path = String::from_utf8(output.stdout)?;
+
+    if !output.status.success() || path.is_empty() {
+        return Err(anyhow!("no font matches {pattern}"));
+    }
+
+    Ok(path)
+}
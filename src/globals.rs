@@ -1,3 +1,17 @@
 pub const CHARACTER_BUFFER_WIDTH: usize = 1024;
 pub const CHARACTER_BUFFER_HEIGHT: usize = 1024;
 pub const CHARACTER_BUFFER_SIZE: usize = CHARACTER_BUFFER_WIDTH * CHARACTER_BUFFER_HEIGHT;
+pub const CONFIG_DIRECTORY: &str = "miniterm1999";
+pub const CONFIG_FILE: &str = "config.toml";
+pub const CONFIG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+pub const EFFECT_DIRECTORY: &str = "shaders";
+pub const EFFECT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+pub const FONT_VARIABLE: &str = "MINITERM1999_FONT";
+pub const FALLBACK_FONTS_VARIABLE: &str = "MINITERM1999_FALLBACK_FONTS";
+pub const FONT_SIZE_VARIABLE: &str = "MINITERM1999_FONT_SIZE";
+pub const LINE_SPACING_VARIABLE: &str = "MINITERM1999_LINE_SPACING";
+pub const LETTER_SPACING_VARIABLE: &str = "MINITERM1999_LETTER_SPACING";
+pub const SCROLLBACK_SIZE: usize = 10000;
+pub const PTY_RESIZE_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(100);
+// seconds, must match BLINK_PERIOD in shader_base.wgsl
+pub const BLINK_PERIOD: f32 = 1.0;
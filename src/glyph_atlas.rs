@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use wgpu::*;
+
+use crate::font::{GlyphSource, Tile};
+
+// the atlas is made wider or taller at first for tiles that wouldn't fit
+const ATLAS_WIDTH: u32 = 1024;
+const INITIAL_ATLAS_HEIGHT: u32 = 256;
+// set on the slots of glyphs with their own colours, must match COLOR_GLYPH in shader_base.wgsl
+const COLOR_GLYPH: u32 = 1 << 31;
+
+/// Texture holding the glyphs in use, each in a slot the size of a tile. Glyphs are rasterised
+/// when first used, and the least recently used ones are evicted once the atlas can't grow.
+pub struct GlyphAtlas {
+    texture: Texture,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+
+    source: Box<dyn GlyphSource>,
+    tile: Tile,
+    columns: u32,
+
+    // slot of each code with COLOR_GLYPH for colour glyphs, the first slot stays empty for blank
+    // cells
+    slots: HashMap<u32, u32>,
+    codes: Vec<Option<u32>>,
+    allocated: u32,
+    last_used: Vec<u64>,
+    frame: u64,
+    evicted: bool,
+}
+
+impl GlyphAtlas {
+    /// Creates an atlas for the glyphs of `source`, with `spacing` added between letters and lines.
+    /// Fails if the glyphs are too large for a texture.
+    pub fn new(device: &Device, source: Box<dyn GlyphSource>, spacing: (i32, i32)) -> Result<Self> {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Glyph Atlas Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (tile, size) = Self::create_tile(device, source.cell_size(), spacing)?;
+        let texture = Self::create_texture(device, size);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture, &sampler);
+        let columns = size.0 / tile.width;
+        let capacity = (columns * (size.1 / tile.height)) as usize;
+
+        Ok(Self {
+            texture,
+            sampler,
+            bind_group_layout,
+            bind_group,
+
+            source,
+            tile,
+            columns,
+
+            slots: HashMap::new(),
+            codes: vec![None; capacity],
+            allocated: 1,
+            last_used: vec![0; capacity],
+            frame: 1,
+            evicted: false,
+        })
+    }
+
+    /// Replaces the glyphs with the ones of `source`, leaving all the slots in use evicted. Fails
+    /// without changing anything if the glyphs are too large for a texture.
+    pub fn set_source(
+        &mut self,
+        device: &Device,
+        source: Box<dyn GlyphSource>,
+        spacing: (i32, i32),
+    ) -> Result<()> {
+        let (tile, size) = Self::create_tile(device, source.cell_size(), spacing)?;
+
+        self.texture.destroy();
+        self.texture = Self::create_texture(device, size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.texture,
+            &self.sampler,
+        );
+
+        self.tile = tile;
+        self.source = source;
+        self.columns = size.0 / self.tile.width;
+
+        let capacity = (self.columns * (size.1 / self.tile.height)) as usize;
+        self.slots.clear();
+        self.codes = vec![None; capacity];
+        self.allocated = 1;
+        self.last_used = vec![0; capacity];
+        self.evicted = true;
+
+        Ok(())
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        self.tile.cell_size()
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Starts a new round of glyph lookups. Glyphs looked up in the current round are never
+    /// evicted, unless the atlas is full of them.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Returns whether glyphs were evicted since the last call, which can leave cells using
+    /// their slots for other glyphs.
+    pub fn take_evicted(&mut self) -> bool {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Returns the slot holding the glyph of `code`, rasterising `cluster` if it isn't there yet.
+    /// Colour glyphs have COLOR_GLYPH set.
+    pub fn glyph(&mut self, device: &Device, queue: &Queue, code: u32, cluster: &str) -> u32 {
+        if let Some(&glyph) = self.slots.get(&code) {
+            self.last_used[(glyph & !COLOR_GLYPH) as usize] = self.frame;
+            return glyph;
+        }
+
+        let slot = self.allocate(device, queue);
+        self.codes[slot as usize] = Some(code);
+        self.last_used[slot as usize] = self.frame;
+
+        self.tile.clear();
+        self.source.draw(cluster, &mut self.tile);
+        self.upload(queue, slot);
+
+        let glyph = if self.tile.colored {
+            slot | COLOR_GLYPH
+        } else {
+            slot
+        };
+        self.slots.insert(code, glyph);
+
+        glyph
+    }
+
+    fn allocate(&mut self, device: &Device, queue: &Queue) -> u32 {
+        if (self.allocated as usize) < self.codes.len() {
+            self.allocated += 1;
+            return self.allocated - 1;
+        }
+
+        let (lru, &last_used) = self
+            .last_used
+            .iter()
+            .enumerate()
+            .skip(1)
+            .min_by_key(|(_, &last_used)| last_used)
+            .expect("the atlas has slots");
+
+        if last_used == self.frame && self.grow(device, queue) {
+            return self.allocate(device, queue);
+        }
+
+        if let Some(code) = self.codes[lru].take() {
+            self.slots.remove(&code);
+        }
+        self.evicted = true;
+
+        lru as u32
+    }
+
+    // Doubles the height of the atlas, returns false if it's already as large as it can be
+    fn grow(&mut self, device: &Device, queue: &Queue) -> bool {
+        let height = self.texture.height() * 2;
+
+        if height > device.limits().max_texture_dimension_2d {
+            return false;
+        }
+
+        let texture = Self::create_texture(device, (self.texture.width(), height));
+        let mut commands = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Glyph Atlas Grow Command Encoder"),
+        });
+        commands.copy_texture_to_texture(
+            self.texture.as_image_copy(),
+            texture.as_image_copy(),
+            self.texture.size(),
+        );
+        queue.submit(std::iter::once(commands.finish()));
+
+        self.texture.destroy();
+        self.texture = texture;
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.texture,
+            &self.sampler,
+        );
+
+        let capacity = (self.columns * (height / self.tile.height)) as usize;
+        self.codes.resize(capacity, None);
+        self.last_used.resize(capacity, 0);
+
+        true
+    }
+
+    fn upload(&self, queue: &Queue, slot: u32) {
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: slot % self.columns * self.tile.width,
+                    y: slot / self.columns * self.tile.height,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            &self.tile.pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(self.tile.width * 4),
+                rows_per_image: Some(self.tile.height),
+            },
+            Extent3d {
+                width: self.tile.width,
+                height: self.tile.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    // Tile for cells of `cell_size`, and the size of an empty atlas for it, with room for at
+    // least the empty slot and one glyph
+    fn create_tile(
+        device: &Device,
+        cell_size: (u32, u32),
+        spacing: (i32, i32),
+    ) -> Result<(Tile, (u32, u32))> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let too_large = |(width, height): (u32, u32)| {
+            anyhow!("cells of {width}x{height} pixels are too large for the glyph atlas")
+        };
+
+        // before the tile is allocated
+        if cell_size.0 > max_size || cell_size.1 > max_size {
+            return Err(too_large(cell_size));
+        }
+
+        let tile = Tile::new(cell_size, spacing);
+        let width = ATLAS_WIDTH.max(tile.width);
+        let rows = if width / tile.width > 1 { 1 } else { 2 };
+        let height = INITIAL_ATLAS_HEIGHT.max(tile.height * rows);
+
+        if width > max_size || height > max_size {
+            return Err(too_large(tile.cell_size()));
+        }
+
+        Ok((tile, (width, height)))
+    }
+
+    fn create_texture(device: &Device, (width, height): (u32, u32)) -> Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        texture: &Texture,
+        sampler: &Sampler,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Glyph Atlas Bind Group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(
+                        &texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+// Codes past the last code point refer to grapheme clusters of more than one code point
+const FIRST_CLUSTER: u32 = 0x110000;
+// Number of clusters interned before the unused ones are dropped
+const MIN_COLLECT_SIZE: usize = 4096;
+
+/// Interns the grapheme clusters printed, so a cell can store one in its `code`.
+pub struct Graphemes {
+    clusters: HashMap<u32, String>,
+    codes: HashMap<String, u32>,
+    // codes are never reused, as the glyph atlas caches glyphs by code
+    next_code: u32,
+    collect_size: usize,
+}
+
+impl Default for Graphemes {
+    fn default() -> Self {
+        Self {
+            clusters: HashMap::new(),
+            codes: HashMap::new(),
+            next_code: FIRST_CLUSTER,
+            collect_size: MIN_COLLECT_SIZE,
+        }
+    }
+}
+
+impl Graphemes {
+    /// Returns the code for `cluster`, either its code point or an interned cluster.
+    pub fn intern(&mut self, cluster: &str) -> u32 {
+        let mut chars = cluster.chars();
+
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return c as u32;
+        }
+
+        if let Some(&code) = self.codes.get(cluster) {
+            return code;
+        }
+
+        let code = self.next_code;
+        self.next_code = self.next_code.wrapping_add(1).max(FIRST_CLUSTER);
+        self.clusters.insert(code, cluster.to_owned());
+        self.codes.insert(cluster.to_owned(), code);
+        code
+    }
+
+    pub fn get(&self, code: u32) -> Cow<'_, str> {
+        match code {
+            FIRST_CLUSTER.. => Cow::Borrowed(self.clusters.get(&code).map_or("", String::as_str)),
+            _ => Cow::Owned(char::from_u32(code).map(String::from).unwrap_or_default()),
+        }
+    }
+
+    /// Returns whether enough clusters were interned since the last `retain` to drop the unused
+    /// ones.
+    pub fn needs_collect(&self) -> bool {
+        self.clusters.len() >= self.collect_size
+    }
+
+    /// Drops the clusters whose code isn't in `live`.
+    pub fn retain(&mut self, live: impl IntoIterator<Item = u32>) {
+        let live: HashSet<u32> = live
+            .into_iter()
+            .filter(|&code| code >= FIRST_CLUSTER)
+            .collect();
+
+        self.clusters.retain(|code, _| live.contains(code));
+        self.codes.retain(|_, code| live.contains(code));
+        self.collect_size = MIN_COLLECT_SIZE.max(self.clusters.len() * 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain() {
+        let mut graphemes = Graphemes::default();
+        assert_eq!(graphemes.intern("a"), 'a' as u32);
+
+        let kept = graphemes.intern("e\u{301}");
+        let dropped = graphemes.intern("a\u{301}");
+        assert_eq!(graphemes.intern("e\u{301}"), kept);
+
+        graphemes.retain(['a' as u32, kept]);
+        assert_eq!(graphemes.get(kept), "e\u{301}");
+        assert_eq!(graphemes.get(dropped), "");
+        assert_eq!(graphemes.get('a' as u32), "a");
+
+        // a dropped cluster gets a new code, so glyphs cached for the old one aren't reused
+        let readded = graphemes.intern("a\u{301}");
+        assert_ne!(readded, dropped);
+        assert_ne!(readded, kept);
+    }
+
+    #[test]
+    fn needs_collect() {
+        let mut graphemes = Graphemes::default();
+        for i in 0..MIN_COLLECT_SIZE {
+            assert!(!graphemes.needs_collect());
+            graphemes.intern(&format!(
+                "{}\u{301}",
+                char::from_u32(0x100 + i as u32).unwrap()
+            ));
+        }
+        assert!(graphemes.needs_collect());
+
+        graphemes.retain([]);
+        assert!(!graphemes.needs_collect());
+    }
+}
@@ -0,0 +1,289 @@
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+const ESC: u8 = 0x1b;
+
+/// Modes set by the application that change what keys send.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyboardMode {
+    // DECCKM, the cursor keys send SS3 rather than CSI sequences
+    pub application_cursor: bool,
+    // DECKPAM, the keypad's Enter and operators send SS3 sequences rather than their text
+    pub application_keypad: bool,
+}
+
+// xterm modifier parameter: 1 + shift + alt * 2 + ctrl * 4 + logo * 8
+fn modifier_param(modifiers: ModifiersState) -> u8 {
+    1 + modifiers.shift() as u8
+        + modifiers.alt() as u8 * 2
+        + modifiers.ctrl() as u8 * 4
+        + modifiers.logo() as u8 * 8
+}
+
+// Keys sent as `CSI final`, or `SS3 final` in application cursor mode, or `CSI 1;mod final`
+fn cursor_key(final_byte: u8, modifiers: ModifiersState, mode: KeyboardMode) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 if mode.application_cursor => vec![ESC, b'O', final_byte],
+        1 => vec![ESC, b'[', final_byte],
+        param => format!("\x1b[1;{param}{}", final_byte as char).into_bytes(),
+    }
+}
+
+// Keys sent as `SS3 final` or `CSI 1;mod final`
+fn ss3_key(final_byte: u8, modifiers: ModifiersState) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 => vec![ESC, b'O', final_byte],
+        param => format!("\x1b[1;{param}{}", final_byte as char).into_bytes(),
+    }
+}
+
+// Keys sent as `CSI code ~` or `CSI code;mod ~`
+fn tilde_key(code: u8, modifiers: ModifiersState) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 => format!("\x1b[{code}~").into_bytes(),
+        param => format!("\x1b[{code};{param}~").into_bytes(),
+    }
+}
+
+fn with_alt(bytes: &[u8], modifiers: ModifiersState) -> Vec<u8> {
+    if modifiers.alt() {
+        [&[ESC], bytes].concat()
+    } else {
+        bytes.to_vec()
+    }
+}
+
+fn ctrl_byte(key: VirtualKeyCode) -> Option<u8> {
+    use VirtualKeyCode::*;
+
+    Some(match key {
+        A => 0x01,
+        B => 0x02,
+        C => 0x03,
+        D => 0x04,
+        E => 0x05,
+        F => 0x06,
+        G => 0x07,
+        H => 0x08,
+        I => 0x09,
+        J => 0x0a,
+        K => 0x0b,
+        L => 0x0c,
+        M => 0x0d,
+        N => 0x0e,
+        O => 0x0f,
+        P => 0x10,
+        Q => 0x11,
+        R => 0x12,
+        S => 0x13,
+        T => 0x14,
+        U => 0x15,
+        V => 0x16,
+        W => 0x17,
+        X => 0x18,
+        Y => 0x19,
+        Z => 0x1a,
+        Space | Key2 | At => 0x00,
+        LBracket | Key3 => 0x1b,
+        Backslash | Key4 => 0x1c,
+        RBracket | Key5 => 0x1d,
+        Key6 | Caret => 0x1e,
+        Minus | Slash | Key7 => 0x1f,
+        Key8 => 0x7f,
+        _ => return None,
+    })
+}
+
+// Final byte of the SS3 sequence a keypad key sends in application keypad mode
+fn keypad_final_byte(key: VirtualKeyCode) -> Option<u8> {
+    use VirtualKeyCode::*;
+
+    Some(match key {
+        NumpadEnter => b'M',
+        NumpadMultiply => b'j',
+        NumpadAdd => b'k',
+        NumpadComma => b'l',
+        NumpadSubtract => b'm',
+        NumpadDecimal => b'n',
+        NumpadDivide => b'o',
+        NumpadEquals => b'X',
+        _ => return None,
+    })
+}
+
+/// Returns whether `key` sends a sequence in place of the text it types in `mode`, so that text
+/// must not be sent too.
+pub fn replaces_text(key: VirtualKeyCode, mode: KeyboardMode) -> bool {
+    mode.application_keypad && keypad_final_byte(key).is_some()
+}
+
+/// Encodes a non-printable key press as the bytes an xterm-compatible terminal would send.
+/// Printable text is delivered separately through [`encode_char`].
+pub fn encode_key(
+    key: VirtualKeyCode,
+    modifiers: ModifiersState,
+    mode: KeyboardMode,
+) -> Option<Vec<u8>> {
+    use VirtualKeyCode::*;
+
+    if mode.application_keypad {
+        if let Some(final_byte) = keypad_final_byte(key) {
+            return Some(ss3_key(final_byte, modifiers));
+        }
+    }
+
+    Some(match key {
+        Return | NumpadEnter => with_alt(b"\r", modifiers),
+        Back if modifiers.ctrl() => with_alt(&[0x08], modifiers),
+        Back => with_alt(&[0x7f], modifiers),
+        Tab if modifiers.shift() => b"\x1b[Z".to_vec(),
+        Tab => with_alt(b"\t", modifiers),
+        Escape => with_alt(&[ESC], modifiers),
+
+        Up => cursor_key(b'A', modifiers, mode),
+        Down => cursor_key(b'B', modifiers, mode),
+        Right => cursor_key(b'C', modifiers, mode),
+        Left => cursor_key(b'D', modifiers, mode),
+        Home => cursor_key(b'H', modifiers, mode),
+        End => cursor_key(b'F', modifiers, mode),
+
+        Insert => tilde_key(2, modifiers),
+        Delete => tilde_key(3, modifiers),
+        PageUp => tilde_key(5, modifiers),
+        PageDown => tilde_key(6, modifiers),
+
+        F1 => ss3_key(b'P', modifiers),
+        F2 => ss3_key(b'Q', modifiers),
+        F3 => ss3_key(b'R', modifiers),
+        F4 => ss3_key(b'S', modifiers),
+        F5 => tilde_key(15, modifiers),
+        F6 => tilde_key(17, modifiers),
+        F7 => tilde_key(18, modifiers),
+        F8 => tilde_key(19, modifiers),
+        F9 => tilde_key(20, modifiers),
+        F10 => tilde_key(21, modifiers),
+        F11 => tilde_key(23, modifiers),
+        F12 => tilde_key(24, modifiers),
+
+        key if modifiers.ctrl() => with_alt(&[ctrl_byte(key)?], modifiers),
+        _ => return None,
+    })
+}
+
+/// Encodes text received from the window. Control characters and Ctrl combinations are
+/// skipped, as they are already handled by [`encode_key`].
+pub fn encode_char(c: char, modifiers: ModifiersState) -> Option<Vec<u8>> {
+    if c.is_control() || modifiers.ctrl() {
+        return None;
+    }
+
+    let mut buffer = [0u8; 4];
+    Some(with_alt(c.encode_utf8(&mut buffer).as_bytes(), modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: ModifiersState = ModifiersState::empty();
+    const SHIFT: ModifiersState = ModifiersState::SHIFT;
+    const CTRL: ModifiersState = ModifiersState::CTRL;
+    const ALT: ModifiersState = ModifiersState::ALT;
+    const LOGO: ModifiersState = ModifiersState::LOGO;
+
+    const NORMAL: KeyboardMode = KeyboardMode {
+        application_cursor: false,
+        application_keypad: false,
+    };
+    const APPLICATION: KeyboardMode = KeyboardMode {
+        application_cursor: true,
+        application_keypad: true,
+    };
+
+    #[test]
+    fn keys() {
+        use VirtualKeyCode::*;
+
+        for (key, modifiers, mode, expected) in [
+            (Up, NONE, NORMAL, &b"\x1b[A"[..]),
+            (Left, NONE, NORMAL, b"\x1b[D"),
+            (Home, NONE, NORMAL, b"\x1b[H"),
+            (End, NONE, NORMAL, b"\x1b[F"),
+            (Up, SHIFT, NORMAL, b"\x1b[1;2A"),
+            (Down, ALT, NORMAL, b"\x1b[1;3B"),
+            (Up, CTRL, NORMAL, b"\x1b[1;5A"),
+            (Right, SHIFT.union(ALT), NORMAL, b"\x1b[1;4C"),
+            (Left, CTRL.union(SHIFT), NORMAL, b"\x1b[1;6D"),
+            (End, LOGO, NORMAL, b"\x1b[1;9F"),
+            (Up, NONE, APPLICATION, b"\x1bOA"),
+            (Home, NONE, APPLICATION, b"\x1bOH"),
+            (Up, CTRL, APPLICATION, b"\x1b[1;5A"),
+            (Delete, NONE, NORMAL, b"\x1b[3~"),
+            (PageUp, CTRL, NORMAL, b"\x1b[5;5~"),
+            (F1, NONE, NORMAL, b"\x1bOP"),
+            (F4, SHIFT, NORMAL, b"\x1b[1;2S"),
+            (F5, CTRL, NORMAL, b"\x1b[15;5~"),
+            (F12, NONE, NORMAL, b"\x1b[24~"),
+            (Return, NONE, NORMAL, b"\r"),
+            (Return, ALT, NORMAL, b"\x1b\r"),
+            (NumpadEnter, NONE, NORMAL, b"\r"),
+            (NumpadEnter, NONE, APPLICATION, b"\x1bOM"),
+            (NumpadAdd, NONE, APPLICATION, b"\x1bOk"),
+            (Back, NONE, NORMAL, b"\x7f"),
+            (Back, CTRL, NORMAL, b"\x08"),
+            (Back, ALT, NORMAL, b"\x1b\x7f"),
+            (Tab, SHIFT, NORMAL, b"\x1b[Z"),
+            (Escape, NONE, NORMAL, b"\x1b"),
+            (A, CTRL, NORMAL, b"\x01"),
+            (Z, CTRL, NORMAL, b"\x1a"),
+            (Space, CTRL, NORMAL, b"\x00"),
+            (A, CTRL.union(ALT), NORMAL, b"\x1b\x01"),
+        ] {
+            assert_eq!(
+                encode_key(key, modifiers, mode).as_deref(),
+                Some(expected),
+                "{key:?} {modifiers:?} {mode:?}"
+            );
+        }
+
+        for (key, modifiers, mode) in [
+            (A, NONE, NORMAL),
+            (A, ALT, NORMAL),
+            (NumpadAdd, NONE, NORMAL),
+        ] {
+            assert_eq!(
+                encode_key(key, modifiers, mode),
+                None,
+                "{key:?} {modifiers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn text_replaced_by_keypad() {
+        assert!(replaces_text(VirtualKeyCode::NumpadAdd, APPLICATION));
+        assert!(!replaces_text(VirtualKeyCode::NumpadAdd, NORMAL));
+        assert!(!replaces_text(VirtualKeyCode::Numpad1, APPLICATION));
+    }
+
+    #[test]
+    fn chars() {
+        for (c, modifiers, expected) in [
+            ('a', NONE, Some(&b"a"[..])),
+            ('A', SHIFT, Some(b"A")),
+            ('a', ALT, Some(b"\x1ba")),
+            ('é', NONE, Some("é".as_bytes())),
+            ('é', ALT, Some("\x1bé".as_bytes())),
+            ('a', CTRL, None),
+            ('a', CTRL.union(ALT), None),
+            ('\r', NONE, None),
+            ('\x7f', NONE, None),
+            ('\x1b', ALT, None),
+        ] {
+            assert_eq!(
+                encode_char(c, modifiers).as_deref(),
+                expected,
+                "{c:?} {modifiers:?}"
+            );
+        }
+    }
+}
@@ -1,21 +1,36 @@
-use std::{io::Write, process::Command};
+use std::io::Write;
 
 use anyhow::Result;
+use config::{Action, Config, ConfigWatcher};
 use state::State;
 use try_read::TryReader;
 use wgpu::SurfaceError;
 use winit::{
     event::*,
     event_loop::*,
-    window::{Fullscreen, WindowBuilder},
+    window::{Fullscreen, UserAttentionType, WindowBuilder},
 };
 
+mod bitmap_font;
+mod box_drawing;
 mod character;
 mod character_buffer;
+mod config;
+mod cursor;
+mod effect;
+mod effect_chain;
+mod font;
 mod globals;
+mod glyph_atlas;
+mod grapheme;
+mod keyboard;
+mod overlay;
+mod palette;
+mod parser;
 mod shader_param;
 mod state;
-mod texture;
+mod terminal;
+mod truetype_font;
 mod try_read;
 mod vertex;
 
@@ -23,17 +38,26 @@ mod vertex;
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let process = ptyprocess::PtyProcess::spawn(Command::new(std::env::var("SHELL")?))?;
-    let reader = TryReader::new(process.get_pty_stream()?);
+    let config_path = Config::path()?;
+    let mut config = Config::load(&config_path).unwrap_or_else(|error| {
+        tracing::error!("{error:#}, using the default config");
+        Config::default()
+    });
+    let mut config_watcher = ConfigWatcher::new(config_path);
 
-    let mut char_buffer = [0u8; 4];
+    let mut process = ptyprocess::PtyProcess::spawn(config.shell.command()?)?;
+    process.set_echo(true, None)?;
+    let reader = TryReader::new(process.get_pty_stream()?);
+    let mut writer = process.get_pty_stream()?;
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("miniterm 1999")
         .build(&event_loop)?;
 
-    let mut state = State::new(&window).await?;
+    let mut state = State::new(&window, &config).await?;
+    // set when a key press already sent a sequence for the text it types
+    let mut skip_text = false;
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::RedrawRequested(window_id) if window_id == window.id() => match state.render() {
@@ -56,20 +80,40 @@ async fn main() -> Result<()> {
                     input:
                         KeyboardInput {
                             state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::F11),
+                            virtual_keycode: Some(key),
                             ..
                         },
                     ..
-                } => {
-                    window.set_fullscreen(match window.fullscreen() {
-                        Some(_) => None,
-                        None => Some(Fullscreen::Borderless(None)),
-                    });
-                }
+                } => match config.action(*key, state.modifiers()) {
+                    Some(Action::ToggleFullscreen) => {
+                        window.set_fullscreen(match window.fullscreen() {
+                            Some(_) => None,
+                            None => Some(Fullscreen::Borderless(None)),
+                        });
+                    }
+                    Some(Action::ScrollPageUp) => state.scroll_page(true),
+                    Some(Action::ScrollPageDown) => state.scroll_page(false),
+                    Some(Action::ScrollToBottom) => state.scroll_view_to_bottom(),
+                    Some(Action::NextEffect) => state.next_effect(),
+                    None => {
+                        let mode = state.keyboard_mode();
+                        skip_text = keyboard::replaces_text(*key, mode);
+                        if let Some(bytes) = keyboard::encode_key(*key, state.modifiers(), mode) {
+                            writer.write_all(&bytes).unwrap_or_else(|e| {
+                                eprintln!("Could not write key to stdin of pty: {e}")
+                            });
+                            state.scroll_view_to_bottom();
+                        }
+                    }
+                },
+                WindowEvent::ReceivedCharacter(_) if skip_text => skip_text = false,
                 WindowEvent::ReceivedCharacter(c) => {
-                    // stream
-                    //     .write_all(c.encode_utf8(&mut char_buffer).as_bytes())
-                    //     .unwrap_or_else(|e| eprintln!("Could not write char to stdin of pty: {e}"));
+                    if let Some(bytes) = keyboard::encode_char(*c, state.modifiers()) {
+                        writer.write_all(&bytes).unwrap_or_else(|e| {
+                            eprintln!("Could not write char to stdin of pty: {e}")
+                        });
+                        state.scroll_view_to_bottom();
+                    }
                 }
                 _ => {}
             }
@@ -83,6 +127,26 @@ async fn main() -> Result<()> {
                 state.push_str(&str);
             }
 
+            if let Some((cols, rows)) = state.take_grid_resize(globals::PTY_RESIZE_DEBOUNCE) {
+                process
+                    .set_window_size(cols as u16, rows as u16)
+                    .unwrap_or_else(|e| eprintln!("Could not resize pty: {e}"));
+            }
+
+            if let Some(reloaded) = config_watcher.poll() {
+                match reloaded.and_then(|reloaded| {
+                    state.apply_config(&reloaded)?;
+                    Ok(reloaded)
+                }) {
+                    Ok(reloaded) => config = reloaded,
+                    Err(error) => tracing::error!("{error:#}, keeping the previous config"),
+                }
+            }
+
+            if state.take_bell() {
+                window.request_user_attention(Some(UserAttentionType::Informational));
+            }
+
             state.update();
             window.request_redraw();
         }
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use wgpu::*;
+
+use crate::{
+    bitmap_font::BitmapFont,
+    font::{GlyphSource, Tile},
+    vertex::Vertex,
+};
+
+// the text is drawn with the bundled font at this scale, so it doesn't depend on the config
+const FONT_SCALE: u32 = 2;
+const MARGIN: u32 = 8;
+const MAX_COLUMNS: usize = 160;
+const MAX_LINES: usize = 60;
+const TEXT_COLOR: [u8; 3] = [255, 112, 112];
+const BACKGROUND_ALPHA: u8 = 208;
+
+/// Text drawn over the top left corner of the window, like the errors of an effect.
+pub struct Overlay {
+    font: BitmapFont,
+    pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    // the text, none while hidden
+    bind_group: Option<BindGroup>,
+}
+
+impl Overlay {
+    pub fn new(device: &Device, format: TextureFormat) -> Result<Self> {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Overlay Bind Group Layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: TextureViewDimension::D2,
+                    sample_type: TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("shader_overlay.wgsl"));
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Overlay Render Pipeline"),
+            layout: Some(&layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Ok(Self {
+            font: BitmapFont::bundled()?,
+            pipeline,
+            bind_group_layout,
+            bind_group: None,
+        })
+    }
+
+    /// Shows `text`, cut to MAX_LINES of MAX_COLUMNS, or hides the overlay without one.
+    pub fn set_text(&mut self, device: &Device, queue: &Queue, text: Option<&str>) {
+        let Some(text) = text else {
+            self.bind_group = None;
+            return;
+        };
+
+        let lines: Vec<Vec<char>> = text
+            .lines()
+            .take(MAX_LINES)
+            .map(|line| {
+                line.replace('\t', "    ")
+                    .chars()
+                    .take(MAX_COLUMNS)
+                    .collect()
+            })
+            .collect();
+        let columns = lines.iter().map(Vec::len).max().unwrap_or_default() as u32;
+
+        let font_cell_size = self.font.cell_size();
+        let cell_size = (font_cell_size.0 * FONT_SCALE, font_cell_size.1 * FONT_SCALE);
+        let width = columns * cell_size.0 + MARGIN * 2;
+        let height = lines.len() as u32 * cell_size.1 + MARGIN * 2;
+
+        let mut pixels = [0, 0, 0, BACKGROUND_ALPHA].repeat((width * height) as usize);
+        let mut tile = Tile::new(cell_size, (0, 0));
+
+        for (row, line) in lines.iter().enumerate() {
+            for (column, character) in line.iter().enumerate() {
+                tile.clear();
+                if !self.font.draw(&character.to_string(), &mut tile) {
+                    continue;
+                }
+
+                let origin = (
+                    MARGIN + column as u32 * cell_size.0,
+                    MARGIN + row as u32 * cell_size.1,
+                );
+                for y in 0..cell_size.1 {
+                    for x in 0..cell_size.0 {
+                        let coverage = tile.pixels[((y * tile.width + x) * 4) as usize] as u32;
+                        let index = (((origin.1 + y) * width + origin.0 + x) * 4) as usize;
+
+                        // text over the background, both premultiplied
+                        for channel in 0..3 {
+                            pixels[index + channel] =
+                                (TEXT_COLOR[channel] as u32 * coverage / 255) as u8;
+                        }
+                        pixels[index + 3] =
+                            (coverage + BACKGROUND_ALPHA as u32 * (255 - coverage) / 255) as u8;
+                    }
+                }
+            }
+        }
+
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Overlay Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        self.bind_group = Some(device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Overlay Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(
+                    &texture.create_view(&TextureViewDescriptor::default()),
+                ),
+            }],
+        }));
+    }
+
+    /// Draws the overlay, if shown, over what's already in `view`.
+    pub fn render(
+        &self,
+        commands: &mut CommandEncoder,
+        view: &TextureView,
+        vertex_buffer: &Buffer,
+    ) {
+        let Some(bind_group) = &self.bind_group else {
+            return;
+        };
+
+        let mut render_pass = commands.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Overlay Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..6, 0..1);
+    }
+}
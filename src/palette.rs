@@ -0,0 +1,102 @@
+#[rustfmt::skip]
+const ANSI_COLORS: [u32; 16] = [
+    0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+    0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// set in the red channel of encoded colours taken from the palette, which is between 0 and 1
+// for RGB ones
+const DEFAULT_MARKER: f32 = -1.0;
+const INDEXED_MARKER: f32 = -2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Default,
+    Indexed(u8),
+    Rgb([f32; 3]),
+}
+
+impl Color {
+    /// Encodes the colour as RGB, the ones taken from the palette as markers, so cells can keep
+    /// them and follow changes to the palette.
+    pub fn encode(self) -> [f32; 3] {
+        match self {
+            Color::Default => [DEFAULT_MARKER, 0.0, 0.0],
+            Color::Indexed(index) => [INDEXED_MARKER, index as f32, 0.0],
+            Color::Rgb(rgb) => rgb,
+        }
+    }
+
+    /// Decodes a colour encoded with `encode`.
+    pub fn decode(color: [f32; 3]) -> Self {
+        match color {
+            [marker, ..] if marker == DEFAULT_MARKER => Color::Default,
+            [marker, index, _] if marker == INDEXED_MARKER => Color::Indexed(index as u8),
+            rgb => Color::Rgb(rgb),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub colors: [[f32; 3]; 256],
+    pub foreground: [f32; 3],
+    pub background: [f32; 3],
+}
+
+impl Palette {
+    pub fn foreground(&self, color: Color) -> [f32; 3] {
+        self.resolve(color, self.foreground)
+    }
+
+    pub fn background(&self, color: Color) -> [f32; 3] {
+        self.resolve(color, self.background)
+    }
+
+    fn resolve(&self, color: Color, default: [f32; 3]) -> [f32; 3] {
+        match color {
+            Color::Default => default,
+            Color::Indexed(index) => self.colors[index as usize],
+            Color::Rgb(rgb) => rgb,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut colors = [[0.0; 3]; 256];
+
+        for (color, hex) in colors.iter_mut().zip(ANSI_COLORS) {
+            *color = hex_to_rgb(hex);
+        }
+
+        for (cube, color) in colors[16..232].iter_mut().enumerate() {
+            *color = rgb(
+                CUBE_LEVELS[cube / 36],
+                CUBE_LEVELS[cube / 6 % 6],
+                CUBE_LEVELS[cube % 6],
+            );
+        }
+
+        for (gray, color) in colors[232..].iter_mut().enumerate() {
+            let level = (8 + gray * 10) as u8;
+            *color = rgb(level, level, level);
+        }
+
+        Self {
+            colors,
+            foreground: [1.0, 1.0, 1.0],
+            background: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+pub fn rgb(r: u8, g: u8, b: u8) -> [f32; 3] {
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]
+}
+
+fn hex_to_rgb(hex: u32) -> [f32; 3] {
+    rgb((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+}
@@ -0,0 +1,428 @@
+// Escape sequence parser following Paul Williams' DEC ANSI parser state machine
+// (https://vt100.net/emu/dec_ansi_parser), extended with xterm's BEL terminated OSC strings
+// and colon separated sub-parameters.
+
+const MAX_INTERMEDIATES: usize = 2;
+const MAX_PARAMS: usize = 32;
+const MAX_OSC_LENGTH: usize = 4096;
+
+pub trait Perform {
+    fn print(&mut self, c: char);
+    fn execute(&mut self, byte: u8);
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char);
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8);
+    fn osc_dispatch(&mut self, params: &[&str], bell_terminated: bool);
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char);
+    fn put(&mut self, c: char);
+    fn unhook(&mut self);
+}
+
+#[derive(Default, Clone)]
+pub struct Params {
+    values: Vec<u16>,
+    group_lengths: Vec<usize>,
+}
+
+impl Params {
+    /// Iterates over the parameters, each one given with its colon separated sub-parameters.
+    pub fn iter(&self) -> impl Iterator<Item = &[u16]> {
+        self.group_lengths.iter().scan(0, |start, &length| {
+            let group = &self.values[*start..*start + length];
+            *start += length;
+            Some(group)
+        })
+    }
+
+    /// Returns the first value of parameter `index`, or `default` if it's missing or zero.
+    pub fn get_or(&self, index: usize, default: u16) -> u16 {
+        match self.iter().nth(index).map(|group| group[0]) {
+            None | Some(0) => default,
+            Some(value) => value,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.group_lengths.clear();
+    }
+
+    fn is_full(&self) -> bool {
+        self.values.len() >= MAX_PARAMS
+    }
+
+    fn push_digit(&mut self, digit: u8) {
+        if self.values.is_empty() {
+            self.start_param();
+        }
+
+        if let Some(value) = self.values.last_mut() {
+            *value = value.saturating_mul(10).saturating_add(digit as u16);
+        }
+    }
+
+    fn push_param(&mut self) {
+        if self.values.is_empty() {
+            self.start_param();
+        }
+
+        self.start_param();
+    }
+
+    fn push_subparam(&mut self) {
+        if self.values.is_empty() {
+            self.start_param();
+        }
+
+        self.values.push(0);
+        if let Some(length) = self.group_lengths.last_mut() {
+            *length += 1;
+        }
+    }
+
+    fn start_param(&mut self) {
+        self.values.push(0);
+        self.group_lengths.push(1);
+    }
+}
+
+impl std::fmt::Debug for Params {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParserState {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    SosPmApcString,
+}
+
+pub struct Parser {
+    state: ParserState,
+    intermediates: Vec<u8>,
+    ignore: bool,
+    params: Params,
+    osc: String,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: ParserState::Ground,
+            intermediates: Vec::with_capacity(MAX_INTERMEDIATES),
+            ignore: false,
+            params: Params::default(),
+            osc: String::new(),
+        }
+    }
+
+    pub fn advance<P: Perform>(&mut self, performer: &mut P, string: &str) {
+        for c in string.chars() {
+            self.advance_char(performer, c);
+        }
+    }
+
+    fn advance_char<P: Perform>(&mut self, performer: &mut P, c: char) {
+        let code = c as u32;
+
+        // "anywhere" transitions
+        match code {
+            0x18 | 0x1a => {
+                self.exit_state(performer, false);
+                performer.execute(code as u8);
+                self.state = ParserState::Ground;
+                return;
+            }
+            0x1b => {
+                self.exit_state(performer, false);
+                self.enter_escape();
+                return;
+            }
+            0x80..=0x9f => {
+                self.exit_state(performer, false);
+                match code {
+                    0x90 => self.enter_dcs(),
+                    0x9b => self.enter_csi(),
+                    0x9d => self.enter_osc(),
+                    0x98 | 0x9e | 0x9f => self.state = ParserState::SosPmApcString,
+                    0x9c => self.state = ParserState::Ground,
+                    _ => {
+                        performer.execute(code as u8);
+                        self.state = ParserState::Ground;
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            ParserState::Ground => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x7f => {}
+                _ => performer.print(c),
+            },
+            ParserState::Escape => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x20..=0x2f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::EscapeIntermediate;
+                }
+                0x50 => self.enter_dcs(),
+                0x58 | 0x5e | 0x5f => self.state = ParserState::SosPmApcString,
+                0x5b => self.enter_csi(),
+                0x5d => self.enter_osc(),
+                0x30..=0x4f | 0x51..=0x57 | 0x59 | 0x5a | 0x5c | 0x60..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, self.ignore, code as u8);
+                    self.state = ParserState::Ground;
+                }
+                _ => {}
+            },
+            ParserState::EscapeIntermediate => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x20..=0x2f => self.collect(code as u8),
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, self.ignore, code as u8);
+                    self.state = ParserState::Ground;
+                }
+                _ => {}
+            },
+            ParserState::CsiEntry => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x20..=0x2f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::CsiIntermediate;
+                }
+                0x30..=0x3b => {
+                    self.param(code as u8);
+                    self.state = ParserState::CsiParam;
+                }
+                0x3c..=0x3f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::CsiParam;
+                }
+                0x40..=0x7e => self.csi_dispatch(performer, c),
+                _ => {}
+            },
+            ParserState::CsiParam => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x20..=0x2f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::CsiIntermediate;
+                }
+                0x30..=0x3b => self.param(code as u8),
+                0x3c..=0x3f => self.state = ParserState::CsiIgnore,
+                0x40..=0x7e => self.csi_dispatch(performer, c),
+                _ => {}
+            },
+            ParserState::CsiIntermediate => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x20..=0x2f => self.collect(code as u8),
+                0x30..=0x3f => self.state = ParserState::CsiIgnore,
+                0x40..=0x7e => self.csi_dispatch(performer, c),
+                _ => {}
+            },
+            ParserState::CsiIgnore => match code {
+                0x00..=0x1f => performer.execute(code as u8),
+                0x40..=0x7e => self.state = ParserState::Ground,
+                _ => {}
+            },
+            ParserState::DcsEntry => match code {
+                0x20..=0x2f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::DcsIntermediate;
+                }
+                0x30..=0x3b => {
+                    self.param(code as u8);
+                    self.state = ParserState::DcsParam;
+                }
+                0x3c..=0x3f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::DcsParam;
+                }
+                0x40..=0x7e => self.hook(performer, c),
+                _ => {}
+            },
+            ParserState::DcsParam => match code {
+                0x20..=0x2f => {
+                    self.collect(code as u8);
+                    self.state = ParserState::DcsIntermediate;
+                }
+                0x30..=0x3b => self.param(code as u8),
+                0x3c..=0x3f => self.state = ParserState::DcsIgnore,
+                0x40..=0x7e => self.hook(performer, c),
+                _ => {}
+            },
+            ParserState::DcsIntermediate => match code {
+                0x20..=0x2f => self.collect(code as u8),
+                0x30..=0x3f => self.state = ParserState::DcsIgnore,
+                0x40..=0x7e => self.hook(performer, c),
+                _ => {}
+            },
+            ParserState::DcsPassthrough => match code {
+                0x7f => {}
+                _ => performer.put(c),
+            },
+            ParserState::DcsIgnore | ParserState::SosPmApcString => {}
+            ParserState::OscString => match code {
+                0x07 => {
+                    self.osc_dispatch(performer, true);
+                    self.state = ParserState::Ground;
+                }
+                0x00..=0x1f => {}
+                _ => {
+                    if self.osc.len() < MAX_OSC_LENGTH {
+                        self.osc.push(c);
+                    }
+                }
+            },
+        }
+    }
+
+    // Runs the exit action of the current state, before an "anywhere" transition leaves it
+    fn exit_state<P: Perform>(&mut self, performer: &mut P, bell_terminated: bool) {
+        match self.state {
+            ParserState::OscString => self.osc_dispatch(performer, bell_terminated),
+            ParserState::DcsPassthrough => performer.unhook(),
+            _ => {}
+        }
+    }
+
+    fn clear(&mut self) {
+        self.intermediates.clear();
+        self.ignore = false;
+        self.params.clear();
+    }
+
+    fn enter_escape(&mut self) {
+        self.clear();
+        self.state = ParserState::Escape;
+    }
+
+    fn enter_csi(&mut self) {
+        self.clear();
+        self.state = ParserState::CsiEntry;
+    }
+
+    fn enter_dcs(&mut self) {
+        self.clear();
+        self.state = ParserState::DcsEntry;
+    }
+
+    fn enter_osc(&mut self) {
+        self.osc.clear();
+        self.state = ParserState::OscString;
+    }
+
+    fn collect(&mut self, byte: u8) {
+        if self.intermediates.len() < MAX_INTERMEDIATES {
+            self.intermediates.push(byte);
+        } else {
+            self.ignore = true;
+        }
+    }
+
+    fn param(&mut self, byte: u8) {
+        match byte {
+            // only a separator would start a parameter past the last one
+            b';' | b':' if self.params.is_full() => self.ignore = true,
+            b';' => self.params.push_param(),
+            b':' => self.params.push_subparam(),
+            digit => self.params.push_digit(digit - b'0'),
+        }
+    }
+
+    fn csi_dispatch<P: Perform>(&mut self, performer: &mut P, action: char) {
+        performer.csi_dispatch(&self.params, &self.intermediates, self.ignore, action);
+        self.state = ParserState::Ground;
+    }
+
+    fn hook<P: Perform>(&mut self, performer: &mut P, action: char) {
+        performer.hook(&self.params, &self.intermediates, self.ignore, action);
+        self.state = ParserState::DcsPassthrough;
+    }
+
+    fn osc_dispatch<P: Perform>(&mut self, performer: &mut P, bell_terminated: bool) {
+        let params: Vec<&str> = self.osc.split(';').collect();
+        performer.osc_dispatch(&params, bell_terminated);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        csi: Vec<(Vec<Vec<u16>>, bool, char)>,
+    }
+
+    impl Perform for Recorder {
+        fn print(&mut self, _: char) {}
+        fn execute(&mut self, _: u8) {}
+        fn csi_dispatch(&mut self, params: &Params, _: &[u8], ignore: bool, action: char) {
+            let params = params.iter().map(<[u16]>::to_vec).collect();
+            self.csi.push((params, ignore, action));
+        }
+        fn esc_dispatch(&mut self, _: &[u8], _: bool, _: u8) {}
+        fn osc_dispatch(&mut self, _: &[&str], _: bool) {}
+        fn hook(&mut self, _: &Params, _: &[u8], _: bool, _: char) {}
+        fn put(&mut self, _: char) {}
+        fn unhook(&mut self) {}
+    }
+
+    fn csi(string: &str) -> (Vec<Vec<u16>>, bool, char) {
+        let mut recorder = Recorder::default();
+        Parser::new().advance(&mut recorder, string);
+        assert_eq!(recorder.csi.len(), 1);
+        recorder.csi.remove(0)
+    }
+
+    fn numbered_params(count: u16) -> String {
+        (1..=count)
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    #[test]
+    fn csi_with_max_params() {
+        let (params, ignore, action) = csi(&format!("\x1b[{}m", numbered_params(32)));
+        assert!(!ignore);
+        assert_eq!(action, 'm');
+        assert_eq!(params.len(), 32);
+        assert_eq!(params[31], [32]);
+    }
+
+    #[test]
+    fn csi_with_too_many_params() {
+        let (_, ignore, _) = csi(&format!("\x1b[{}m", numbered_params(33)));
+        assert!(ignore);
+
+        let (_, ignore, _) = csi(&format!("\x1b[{}:1m", numbered_params(32)));
+        assert!(ignore);
+    }
+
+    #[test]
+    fn csi_subparams_and_defaults() {
+        let (params, ignore, _) = csi("\x1b[38:2:255:128:0;;4m");
+        assert!(!ignore);
+        assert_eq!(params, [vec![38, 2, 255, 128, 0], vec![0], vec![4]]);
+
+        let (params, _, action) = csi("\x1b[H");
+        assert!(params.is_empty());
+        assert_eq!(action, 'H');
+    }
+}
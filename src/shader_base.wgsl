@@ -18,54 +18,263 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 // // // FRAGMENT
 
 const CHARACTER_BUFFER_WIDTH: u32 = 1024u;
-const CHARACTER_WIDTH: f32 = 10.0;
-const CHARACTER_HEIGHT: f32 = 10.0;
-const FONT_TEXTURE_ROW: u32 = 16u;
-const FONT_TEXTURE_WIDTH: f32 = 160.0;
-const FONT_TEXTURE_HEIGHT: f32 = 160.0;
+const TAU: f32 = 6.28318530718;
+// seconds, must match BLINK_PERIOD in globals.rs
+const BLINK_PERIOD: f32 = 1.0;
+
+// Character flags, must match the ones in character.rs
+const BOLD: u32 = 1u;
+const FAINT: u32 = 2u;
+const ITALIC: u32 = 4u;
+// 3 bits holding the underline style: none, single, double, curly, dotted or dashed
+const UNDERLINE_SHIFT: u32 = 3u;
+const UNDERLINE_MASK: u32 = 56u;
+const STRIKETHROUGH: u32 = 64u;
+const OVERLINE: u32 = 128u;
+const INVERSE: u32 = 256u;
+const HIDDEN: u32 = 512u;
+const BLINK: u32 = 1024u;
+const UNDERLINE_COLOR: u32 = 2048u;
+const WIDE: u32 = 4096u;
+const WIDE_SPACER: u32 = 8192u;
+
+// set on the glyphs with their own colours, must match COLOR_GLYPH in glyph_atlas.rs
+const COLOR_GLYPH: u32 = 2147483648u;
 
 struct Character {
 	@location(0) bg: vec3<f32>,
-	@location(1) fg: vec3<f32>,
-	@location(2) code: u32,
+	@location(1) glyph: u32,
+	@location(2) fg: vec3<f32>,
+	@location(3) code: u32,
+	@location(4) underline_color: vec3<f32>,
+	@location(5) flags: u32,
+}
+
+// Cursor flags and shapes, must match the ones in cursor.rs
+const CURSOR_VISIBLE: u32 = 1u;
+const CURSOR_BLINKING: u32 = 2u;
+const CURSOR_FOCUSED: u32 = 4u;
+const CURSOR_BLOCK: u32 = 0u;
+
+struct Cursor {
+	position: vec2<u32>,
+	shape: u32,
+	flags: u32,
+}
+
+struct ShaderParam {
+	screen_size: vec2<u32>,
+	// seconds since the start, and since the previous frame
+	time: f32,
+	delta_time: f32,
+	// pixels of the screen
+	cell_size: vec2<f32>,
+	// columns and rows
+	grid_size: vec2<u32>,
+	// cell, from the top left corner of the grid
+	cursor_position: vec2<u32>,
+	// pixels, from the top left corner of the window
+	mouse_position: vec2<f32>,
+	// 0 for a block, 1 for an underline, 2 for a bar
+	cursor_shape: u32,
+	cursor_visible: u32,
+	focused: u32,
+	frame: u32,
+	// seconds since the last output of the program and the last bell, a very long time if
+	// there was none
+	time_since_output: f32,
+	time_since_bell: f32,
+	scale_factor: f32,
 }
 
 @group(0) @binding(0)
-var t_font: texture_2d<f32>;
+var t_atlas: texture_2d<f32>;
 @group(0) @binding(1)
-var s_font: sampler;
+var s_atlas: sampler;
 
 @group(1) @binding(0)
 var<storage, read> characters: array<Character>;
 @group(1) @binding(1)
 var<uniform> scale_factor: f32;
+@group(1) @binding(2)
+var<storage, read> rows: array<u32>;
+@group(1) @binding(3)
+var<uniform> cursor: Cursor;
+// pixels, from the metrics of the font
+@group(1) @binding(4)
+var<uniform> cell_size: vec2<f32>;
+
+@group(2) @binding(0)
+var<uniform> shader_param: ShaderParam;
 
 fn get_character(x: u32, y: u32) -> Character {
-	return characters[y * CHARACTER_BUFFER_WIDTH + x];
+	return characters[rows[y] * CHARACTER_BUFFER_WIDTH + x];
+}
+
+// Top left corner of an atlas slot, in pixels
+fn get_glyph_origin(glyph: u32) -> vec2<f32> {
+	// glyphs are two cells wide in the atlas, so wide characters fit
+	let slot_size = cell_size * vec2(2.0, 1.0);
+	let columns = u32(f32(textureDimensions(t_atlas).x) / slot_size.x);
+	let slot = glyph & ~COLOR_GLYPH;
+	return vec2(f32(slot % columns), f32(slot / columns)) * slot_size;
+}
+
+// Premultiplied colour of the glyph at `pos`, in pixels from the top left corner of the cell.
+// Glyphs without their own colours are white, with their coverage as alpha.
+fn glyph_at(glyph: u32, width: f32, pos: vec2<f32>) -> vec4<f32> {
+	if pos.x < 0.0 || pos.x >= width || pos.y < 0.0 || pos.y >= cell_size.y {
+		return vec4(0.0);
+	}
+
+	return textureSampleLevel(t_atlas, s_atlas,
+		(get_glyph_origin(glyph) + pos) / vec2<f32>(textureDimensions(t_atlas)),
+		0.0
+	);
+}
+
+fn underline_at(style: u32, pos: vec2<f32>) -> bool {
+	let row = floor(pos.y);
+	let column = floor(pos.x);
+	let bottom = cell_size.y - 1.0;
+
+	switch style {
+		// single
+		case 1u: {
+			return row == bottom;
+		}
+		// double
+		case 2u: {
+			return row == bottom || row == bottom - 2.0;
+		}
+		// curly
+		case 3u: {
+			return abs(pos.y - (bottom - 0.5 + sin(pos.x / cell_size.x * TAU))) < 0.75;
+		}
+		// dotted
+		case 4u: {
+			return row == bottom && column % 2.0 == 0.0;
+		}
+		// dashed
+		case 5u: {
+			return row == bottom && column % 5.0 < 3.0;
+		}
+		default: {
+			return false;
+		}
+	}
 }
 
-fn get_tex_coord(code: u32) -> vec2<f32> {
-	return vec2(f32(code % FONT_TEXTURE_ROW), f32(code / FONT_TEXTURE_ROW)) * vec2(CHARACTER_WIDTH, CHARACTER_HEIGHT);
+// Whether the cursor outline covers `pos`, for the shapes not drawn by inverting the cell
+fn cursor_outline_at(pos: vec2<f32>, width: f32, focused: bool) -> bool {
+	let row = floor(pos.y);
+	let column = floor(pos.x);
+
+	switch cursor.shape {
+		// block, hollow when unfocused
+		case 0u: {
+			return !focused && (row == 0.0 || row == cell_size.y - 1.0 || column == 0.0 || column == width - 1.0);
+		}
+		// underline
+		case 1u: {
+			return row == cell_size.y - 1.0;
+		}
+		// bar
+		case 2u: {
+			return column == 0.0;
+		}
+		default: {
+			return false;
+		}
+	}
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-	let char: Character = get_character(
-		u32(floor(in.clip_position.x / CHARACTER_WIDTH * scale_factor)),
-		u32(floor(in.clip_position.y / CHARACTER_HEIGHT * scale_factor))
+	let cell = vec2(
+		u32(floor(in.clip_position.x / cell_size.x * scale_factor)),
+		u32(floor(in.clip_position.y / cell_size.y * scale_factor))
 	);
+	var char: Character = get_character(cell.x, cell.y);
+	var pos = vec2((in.clip_position.x * scale_factor) % cell_size.x, (in.clip_position.y * scale_factor) % cell_size.y);
 
-	if textureSample(t_font, s_font,
-		(
-			get_tex_coord(char.code) +
-			vec2((in.clip_position.x * scale_factor) % CHARACTER_WIDTH, (in.clip_position.y * scale_factor) % CHARACTER_HEIGHT)
-		) / vec2(FONT_TEXTURE_WIDTH, FONT_TEXTURE_HEIGHT)
-	).r > 0.5
-	{
-		return vec4(char.fg, 1.0);
+	// the right half of a wide character is drawn from its first cell
+	var first_cell = cell;
+	if (char.flags & WIDE_SPACER) != 0u && cell.x > 0u {
+		first_cell.x -= 1u;
+		char = get_character(first_cell.x, first_cell.y);
+		pos.x += cell_size.x;
+	}
+
+	var width = cell_size.x;
+	if (char.flags & WIDE) != 0u {
+		width *= 2.0;
 	}
-	else
+
+	var fg = char.fg;
+	var bg = char.bg;
+
+	if (char.flags & INVERSE) != 0u {
+		fg = char.bg;
+		bg = char.fg;
+	}
+
+	if (char.flags & FAINT) != 0u {
+		fg = mix(bg, fg, 0.5);
+	}
+
+	let focused = (cursor.flags & CURSOR_FOCUSED) != 0u;
+	let cursor_blinked_out = focused && (cursor.flags & CURSOR_BLINKING) != 0u && fract(shader_param.time / BLINK_PERIOD) >= 0.5;
+	let cursor_here = (cursor.flags & CURSOR_VISIBLE) != 0u && (all(cell == cursor.position) || all(first_cell == cursor.position)) && !cursor_blinked_out;
+
+	if cursor_here && focused && cursor.shape == CURSOR_BLOCK {
+		let cursor_bg = fg;
+		fg = bg;
+		bg = cursor_bg;
+	}
+
+	var underline_color = fg;
+	if (char.flags & UNDERLINE_COLOR) != 0u {
+		underline_color = char.underline_color;
+	}
+
+	if cursor_here && cursor_outline_at(pos, width, focused) {
+		return vec4(fg, 1.0);
+	}
+
+	let blinked_out = (char.flags & BLINK) != 0u && fract(shader_param.time / BLINK_PERIOD) >= 0.5;
+	if (char.flags & HIDDEN) != 0u || blinked_out {
+		return vec4(bg, 1.0);
+	}
+
+	var glyph_pos = pos;
+	if (char.flags & ITALIC) != 0u {
+		glyph_pos.x -= (cell_size.y * 0.5 - pos.y) * 0.2;
+	}
+
+	var glyph = glyph_at(char.glyph, width, glyph_pos);
+	if (char.flags & BOLD) != 0u {
+		glyph = max(glyph, glyph_at(char.glyph, width, glyph_pos - vec2(1.0, 0.0)));
+	}
+
+	let row = floor(pos.y);
+	if ((char.flags & STRIKETHROUGH) != 0u && row == floor(cell_size.y * 0.5))
+		|| ((char.flags & OVERLINE) != 0u && row == 0.0)
 	{
-		return vec4(char.bg, 1.0);
+		return vec4(fg, 1.0);
+	}
+
+	var color = bg;
+	if underline_at((char.flags & UNDERLINE_MASK) >> UNDERLINE_SHIFT, pos) {
+		color = underline_color;
 	}
+
+	// colour glyphs are drawn over the background, others blend the foreground in by their coverage
+	if (char.glyph & COLOR_GLYPH) != 0u {
+		color = glyph.rgb + color * (1.0 - glyph.a);
+	} else {
+		color = mix(color, fg, glyph.a);
+	}
+
+	return vec4(color, 1.0);
 }
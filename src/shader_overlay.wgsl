@@ -0,0 +1,34 @@
+// // // VERTEX
+
+struct VertexInput {
+	@location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+	var out: VertexOutput;
+	out.clip_position = vec4(in.position, 1.0);
+	return out;
+}
+
+// // // FRAGMENT
+
+@group(0) @binding(0)
+var t_overlay: texture_2d<f32>;
+
+// the overlay is drawn unscaled in the top left corner, in premultiplied alpha
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+	let coords = vec2<u32>(in.clip_position.xy);
+	let size = textureDimensions(t_overlay);
+
+	if coords.x >= size.x || coords.y >= size.y {
+		return vec4(0.0);
+	}
+
+	return textureLoad(t_overlay, coords, 0);
+}
@@ -1,7 +1,26 @@
+// Must match ShaderParam in shader_base.wgsl and shader_final.pre-wgsl
 #[repr(C)]
 #[derive(Default, Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ShaderParam {
     pub screen_size: [u32; 2],
+    // seconds since the start, and since the previous frame
     pub time: f32,
+    pub delta_time: f32,
+    // pixels of the screen
+    pub cell_size: [f32; 2],
+    // columns and rows
+    pub grid_size: [u32; 2],
+    // cell, from the top left corner of the grid
+    pub cursor_position: [u32; 2],
+    // pixels, from the top left corner of the window
+    pub mouse_position: [f32; 2],
+    pub cursor_shape: u32,
+    pub cursor_visible: u32,
+    pub focused: u32,
+    pub frame: u32,
+    // seconds since the last output of the pty and the last bell
+    pub time_since_output: f32,
+    pub time_since_bell: f32,
+    pub scale_factor: f32,
     _padding: u32,
 }
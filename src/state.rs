@@ -1,3 +1,8 @@
+use std::sync::{
+    mpsc::{self, Receiver, TryRecvError},
+    Arc,
+};
+
 use anyhow::Result;
 use wgpu::{util::DeviceExt, *};
 use winit::{
@@ -6,10 +11,26 @@ use winit::{
 };
 
 use crate::{
-    character::Character, character_buffer::CharacterBuffer, shader_param::ShaderParam, texture,
-    vertex::Vertex, vertices,
+    character::Character,
+    config::Config,
+    cursor::Cursor,
+    effect::{self, Effect, EffectWatcher},
+    effect_chain::EffectChain,
+    font::{self, FontSettings},
+    globals,
+    glyph_atlas::GlyphAtlas,
+    keyboard::KeyboardMode,
+    overlay::Overlay,
+    parser::Parser,
+    shader_param::ShaderParam,
+    terminal::Terminal,
+    vertex::Vertex,
+    vertices,
 };
 
+// seconds given as the time since something that never happened
+const NEVER: f32 = 1.0e6;
+
 #[rustfmt::skip]
 const VERTICES: [Vertex; 6] = vertices!(
     (-1.0, -1.0,  0.0),
@@ -24,36 +45,58 @@ pub struct State {
     size: PhysicalSize<u32>,
 
     surface: Surface,
-    device: Device,
+    // shared with the threads building effects
+    device: Arc<Device>,
     queue: Queue,
     config: SurfaceConfiguration,
     base_render_pipeline: RenderPipeline,
-    final_render_pipeline: RenderPipeline,
+    effect_chain: EffectChain,
 
-    texture_bind_group_layout: BindGroupLayout,
+    texture_bind_group_layout: Arc<BindGroupLayout>,
+    shader_param_bind_group_layout: Arc<BindGroupLayout>,
 
     base_texture: Texture,
     base_texture_bind_group: BindGroup,
 
     vertex_buffer: Buffer,
     character_buffer: Buffer,
+    row_buffer: Buffer,
     scale_factor_uniform: Buffer,
+    cell_size_uniform: Buffer,
+    cursor_uniform: Buffer,
     shader_param_buffer: Buffer,
-    font_texture_bind_group: BindGroup,
+    glyph_atlas: GlyphAtlas,
     character_buffer_bind_group: BindGroup,
     shader_param_bind_group: BindGroup,
 
-    characters: CharacterBuffer,
+    parser: Parser,
+    terminal: Terminal,
     scale_factor: f32,
+    cell_size: (u32, u32),
+    font_settings: FontSettings,
+    // the one named in the config, which may not be the one shown after cycling
+    configured_effect: String,
+    effect_watcher: EffectWatcher,
+    // effect chain built in the background, replacing the current one once it's ready
+    building_effect: Option<Receiver<Result<(String, EffectChain)>>>,
+    overlay: Overlay,
     shader_param: ShaderParam,
+    cursor: Cursor,
+    focused: bool,
+    mouse_position: PhysicalPosition<f64>,
+    // fraction of a line scrolled by the wheel or touchpad but not applied yet
+    scroll_remainder: f32,
 
     modifiers_state: ModifiersState,
 
     start_time: std::time::Instant,
+    output_at: Option<std::time::Instant>,
+    bell_at: Option<std::time::Instant>,
+    grid_resized_at: Option<std::time::Instant>,
 }
 
 impl State {
-    pub async fn new(window: &winit::window::Window) -> Result<Self> {
+    pub async fn new(window: &winit::window::Window, config: &Config) -> Result<Self> {
         let size = window.inner_size();
 
         let instance = Instance::new(InstanceDescriptor {
@@ -83,17 +126,17 @@ impl State {
             )
             .await?;
 
-        let config = SurfaceConfiguration {
+        let surface_config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
             format: TextureFormat::Bgra8Unorm,
             width: size.width,
             height: size.height,
-            present_mode: PresentMode::Fifo,
+            present_mode: config.present_mode.into(),
             alpha_mode: CompositeAlphaMode::Opaque,
             view_formats: vec![TextureFormat::Bgra8Unorm],
         };
 
-        surface.configure(&device, &config);
+        surface.configure(&device, &surface_config);
 
         let vertex_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -124,41 +167,36 @@ impl State {
                 label: Some("Texture Bind Group Layout"),
             });
 
-        let font_texture = texture::Texture::from_bytes(
+        let font_settings = config.font.clone().with_env();
+        let glyph_atlas = GlyphAtlas::new(
             &device,
-            &queue,
-            include_bytes!("../assets/font.png"),
-            "Font Texture",
+            Box::new(font::load(&font_settings)?),
+            (font_settings.letter_spacing, font_settings.line_spacing),
         )?;
-
-        let font_texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&font_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&font_texture.sampler),
-                },
-            ],
-            label: Some("Texture Bind Group"),
-        });
+        let cell_size = glyph_atlas.cell_size();
 
         let (base_texture, base_texture_bind_group) =
             Self::create_base_texture(&device, &texture_bind_group_layout, size);
 
-        let scale_factor = 0.5;
+        let scale_factor = config.scale_factor;
 
-        let characters = CharacterBuffer::new((
-            f32::floor((size.width / 10) as f32 * scale_factor) as u32,
-            f32::floor((size.height / 10) as f32 * scale_factor) as u32,
-        ));
+        let terminal = Terminal::new(
+            Self::grid_bounds(size, cell_size, scale_factor),
+            config.scrollback,
+            config.colors.palette(),
+        );
 
-        let character_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+        // filled by the first write_character_changes
+        let character_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("Character Buffer"),
-            contents: bytemuck::cast_slice(characters.buffer()),
+            size: (globals::CHARACTER_BUFFER_SIZE * std::mem::size_of::<Character>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let row_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Row Buffer"),
+            contents: bytemuck::cast_slice(terminal.characters().rows()),
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
         });
 
@@ -168,6 +206,20 @@ impl State {
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
+        let cell_size_uniform = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Cell Size Uniform Buffer"),
+            contents: bytemuck::bytes_of(&[cell_size.0 as f32, cell_size.1 as f32]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let cursor = terminal.cursor(true);
+
+        let cursor_uniform = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Cursor Uniform Buffer"),
+            contents: bytemuck::bytes_of(&cursor),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
         let character_buffer_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("Character Buffer Bind Group Layout"),
@@ -192,6 +244,36 @@ impl State {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -215,6 +297,30 @@ impl State {
                         size: None,
                     }),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &row_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &cursor_uniform,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &cell_size_uniform,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
         });
 
@@ -256,20 +362,13 @@ impl State {
         });
 
         let base_shader = device.create_shader_module(include_wgsl!("shader_base.wgsl"));
-        let final_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Shader Final"),
-            source: ShaderSource::Wgsl(
-                String::from(include_str!("shader_final.pre-wgsl"))
-                    .replacen("//#FXDEF", include_str!("../shaders/vhs"), 1)
-                    .into(),
-            ),
-        });
 
         let base_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Pipeline Layout"),
             bind_group_layouts: &[
-                &texture_bind_group_layout,
+                glyph_atlas.bind_group_layout(),
                 &character_buffer_bind_group_layout,
+                &shader_param_bind_group_layout,
             ],
             push_constant_ranges: &[],
         });
@@ -309,93 +408,141 @@ impl State {
             multiview: None,
         });
 
-        let final_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Pipeline Layout"),
-            bind_group_layouts: &[&texture_bind_group_layout, &shader_param_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        let final_render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Final Render Pipeline"),
-            layout: Some(&final_pipeline_layout),
-            vertex: VertexState {
-                module: &final_shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            fragment: Some(FragmentState {
-                module: &final_shader,
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    format: TextureFormat::Bgra8Unorm,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            multiview: None,
-        });
+        // passthrough until the configured effect is loaded in the background
+        let mut effect_chain = EffectChain::new(
+            &device,
+            &effect::load(effect::NONE)?,
+            [&texture_bind_group_layout, &shader_param_bind_group_layout],
+            surface_config.format,
+        )?;
+        effect_chain.resize(&device, size);
+        let overlay = Overlay::new(&device, surface_config.format)?;
 
         let start_time = std::time::Instant::now();
 
-        let new = Self {
+        let mut new = Self {
             size,
 
             surface,
-            device,
+            device: Arc::new(device),
             queue,
-            config,
+            config: surface_config,
             base_render_pipeline,
-            final_render_pipeline,
+            effect_chain,
 
-            texture_bind_group_layout,
+            texture_bind_group_layout: Arc::new(texture_bind_group_layout),
+            shader_param_bind_group_layout: Arc::new(shader_param_bind_group_layout),
 
             base_texture,
             base_texture_bind_group,
 
             vertex_buffer,
             character_buffer,
+            row_buffer,
             scale_factor_uniform,
+            cell_size_uniform,
+            cursor_uniform,
             shader_param_buffer,
-            font_texture_bind_group,
+            glyph_atlas,
             character_buffer_bind_group,
             shader_param_bind_group,
 
-            characters,
+            parser: Parser::new(),
+            terminal,
             scale_factor,
+            cell_size,
+            font_settings,
+            configured_effect: config.effect.clone(),
+            effect_watcher: EffectWatcher::new(&config.effect),
+            building_effect: None,
+            overlay,
             shader_param,
+            cursor,
+            focused: true,
+            mouse_position: PhysicalPosition::default(),
+            scroll_remainder: 0.0,
 
             modifiers_state: ModifiersState::empty(),
 
             start_time,
+            output_at: None,
+            bell_at: None,
+            // the pty starts with a default size, not the one of the grid
+            grid_resized_at: Some(start_time),
         };
 
-        new.render_base_texture();
+        new.write_character_changes();
 
         Ok(new)
     }
 
+    /// Applies the settings of a reloaded config, all of them or none if the font it names can't
+    /// be loaded. The effect is loaded in the background, with its errors shown over the terminal.
+    /// The shell settings only apply to the next start.
+    pub fn apply_config(&mut self, config: &Config) -> Result<()> {
+        let font_settings = config.font.clone().with_env();
+        let font = if font_settings != self.font_settings {
+            Some(font::load(&font_settings)?)
+        } else {
+            None
+        };
+
+        if let Some(font) = font {
+            self.glyph_atlas.set_source(
+                &self.device,
+                Box::new(font),
+                (font_settings.letter_spacing, font_settings.line_spacing),
+            )?;
+            self.cell_size = self.glyph_atlas.cell_size();
+            self.queue.write_buffer(
+                &self.cell_size_uniform,
+                0,
+                bytemuck::bytes_of(&[self.cell_size.0 as f32, self.cell_size.1 as f32]),
+            );
+            self.font_settings = font_settings;
+        }
+
+        if config.effect != self.configured_effect {
+            self.effect_watcher = EffectWatcher::new(&config.effect);
+            self.building_effect = None;
+            self.configured_effect = config.effect.clone();
+        }
+
+        self.terminal.set_palette(config.colors.palette());
+        self.terminal
+            .characters_mut()
+            .set_scrollback_size(config.scrollback);
+
+        let present_mode = PresentMode::from(config.present_mode);
+        if present_mode != self.config.present_mode {
+            self.config.present_mode = present_mode;
+            self.surface.configure(&self.device, &self.config);
+        }
+
+        self.scale_factor = config.scale_factor;
+        self.queue.write_buffer(
+            &self.scale_factor_uniform,
+            0,
+            bytemuck::bytes_of(&self.scale_factor),
+        );
+
+        self.update_grid_bounds();
+        self.write_character_changes();
+
+        Ok(())
+    }
+
     pub fn input(&mut self, event: winit::event::WindowEvent) -> bool {
         match event {
             winit::event::WindowEvent::MouseWheel { delta, .. } if self.modifiers_state.ctrl() => {
-                self.scale_factor += f32::signum(match delta {
-                    MouseScrollDelta::LineDelta(_, v) => v,
-                    MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => y as f32,
-                }) * -0.1;
+                self.scale_factor = f32::max(
+                    self.scale_factor
+                        + f32::signum(match delta {
+                            MouseScrollDelta::LineDelta(_, v) => v,
+                            MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => y as f32,
+                        }) * -0.1,
+                    0.1,
+                );
 
                 self.queue.write_buffer(
                     &self.scale_factor_uniform,
@@ -403,10 +550,34 @@ impl State {
                     bytemuck::bytes_of(&self.scale_factor),
                 );
 
+                self.update_grid_bounds();
                 self.render_base_texture();
 
                 true
             }
+            winit::event::WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, v) => v * 3.0,
+                    MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => {
+                        y as f32 * self.scale_factor / self.cell_size.1 as f32
+                    }
+                } + self.scroll_remainder;
+
+                // touchpads send small deltas, which only add up to lines together
+                let whole_lines = lines.trunc();
+                self.scroll_remainder = lines - whole_lines;
+                self.scroll_view(whole_lines as i32);
+                true
+            }
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                self.mouse_position = position;
+                false
+            }
+            winit::event::WindowEvent::Focused(focused) => {
+                self.focused = focused;
+                self.update_cursor();
+                true
+            }
             winit::event::WindowEvent::ModifiersChanged(new_state) => {
                 self.modifiers_state = new_state;
                 false
@@ -415,7 +586,7 @@ impl State {
         }
     }
 
-    pub fn render(&self) -> Result<(), SurfaceError> {
+    pub fn render(&mut self) -> Result<(), SurfaceError> {
         let output_texture = self.surface.get_current_texture()?;
         let view = output_texture.texture.create_view(&TextureViewDescriptor {
             label: Some("Output Texture View"),
@@ -428,26 +599,14 @@ impl State {
                 label: Some("Command Encoder"),
             });
 
-        {
-            let mut render_pass = commands.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color::BLACK),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_pipeline(&self.final_render_pipeline);
-            render_pass.set_bind_group(0, &self.base_texture_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.shader_param_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..6, 0..1);
-        }
+        self.effect_chain.render(
+            &mut commands,
+            &view,
+            [&self.base_texture_bind_group, &self.shader_param_bind_group],
+            &self.vertex_buffer,
+        );
+        self.overlay
+            .render(&mut commands, &view, &self.vertex_buffer);
 
         self.queue.submit(std::iter::once(commands.finish()));
         output_texture.present();
@@ -482,9 +641,10 @@ impl State {
             });
 
             render_pass.set_pipeline(&self.base_render_pipeline);
-            render_pass.set_bind_group(0, &self.font_texture_bind_group, &[]);
+            render_pass.set_bind_group(0, self.glyph_atlas.bind_group(), &[]);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             render_pass.set_bind_group(1, &self.character_buffer_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.shader_param_bind_group, &[]);
             render_pass.draw(0..6, 0..1);
         }
 
@@ -496,14 +656,62 @@ impl State {
             .duration_since(self.start_time)
             .as_secs_f32();
 
-        self.shader_param.time = time;
-        self.update_shader_param();
+        let blink_phase = (time * 2.0 / globals::BLINK_PERIOD) as u32;
+        let previous_blink_phase = (self.shader_param.time * 2.0 / globals::BLINK_PERIOD) as u32;
+
+        self.update_shader_param(time);
+
+        if let Some(effect) = self.effect_watcher.poll() {
+            self.build_effect(effect);
+        }
+
+        if let Some(building) = &self.building_effect {
+            match building.try_recv() {
+                Ok(effect_chain) => {
+                    self.building_effect = None;
+                    self.set_effect(effect_chain);
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => self.building_effect = None,
+            }
+        }
+
+        let cursor_blinks = self.cursor.flags
+            & (Cursor::VISIBLE | Cursor::BLINKING | Cursor::FOCUSED)
+            == Cursor::VISIBLE | Cursor::BLINKING | Cursor::FOCUSED;
+
+        if blink_phase != previous_blink_phase && (self.terminal.blink_used() || cursor_blinks) {
+            self.render_base_texture();
+        }
+    }
+
+    // Uploads the cursor if it changed, returns whether it did
+    fn update_cursor(&mut self) -> bool {
+        let cursor = self.terminal.cursor(self.focused);
+
+        if cursor == self.cursor {
+            return false;
+        }
+
+        self.cursor = cursor;
+        self.queue
+            .write_buffer(&self.cursor_uniform, 0, bytemuck::bytes_of(&self.cursor));
+        self.render_base_texture();
+        true
     }
 
     pub fn size(&self) -> PhysicalSize<u32> {
         self.size
     }
 
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers_state
+    }
+
+    pub fn keyboard_mode(&self) -> KeyboardMode {
+        self.terminal.keyboard_mode()
+    }
+
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
@@ -512,33 +720,149 @@ impl State {
             self.surface.configure(&self.device, &self.config);
 
             self.recreate_base_texture();
+            self.effect_chain.resize(&self.device, new_size);
+            self.update_grid_bounds();
             self.render_base_texture();
 
-            self.characters.bounds = (
-                f32::floor((new_size.width / 10) as f32 * self.scale_factor) as u32,
-                f32::floor((new_size.height / 10) as f32 * self.scale_factor) as u32,
-            );
-
             self.shader_param.screen_size = [new_size.width, new_size.height];
-            self.update_shader_param();
+            self.write_shader_param();
+        }
+    }
+
+    /// Returns the grid size to give to the pty once it stopped changing for `debounce`.
+    pub fn take_grid_resize(&mut self, debounce: std::time::Duration) -> Option<(u32, u32)> {
+        match self.grid_resized_at {
+            Some(resized_at) if resized_at.elapsed() >= debounce => {
+                self.grid_resized_at = None;
+                Some(self.terminal.characters().bounds)
+            }
+            _ => None,
         }
     }
 
+    fn update_grid_bounds(&mut self) {
+        let bounds = Self::grid_bounds(self.size, self.cell_size, self.scale_factor);
+
+        if bounds != self.terminal.characters().bounds {
+            self.terminal.characters_mut().set_bounds(bounds);
+            self.upload_characters();
+            self.grid_resized_at = Some(std::time::Instant::now());
+        }
+    }
+
+    fn grid_bounds(
+        size: PhysicalSize<u32>,
+        cell_size: (u32, u32),
+        scale_factor: f32,
+    ) -> (u32, u32) {
+        // a window smaller than a cell still shows one, the pty and grid can't be empty
+        (
+            (f32::floor(size.width as f32 / cell_size.0 as f32 * scale_factor) as u32).max(1),
+            (f32::floor(size.height as f32 / cell_size.1 as f32 * scale_factor) as u32).max(1),
+        )
+    }
+
     pub fn push_str(&mut self, string: &str) {
-        for char in string.chars() {
-            self.characters.push_char(Character::new(
-                [0.0, 0.0, 0.0],
-                [1.0, 1.0, 1.0],
-                char as u32,
-            ));
+        self.output_at = Some(std::time::Instant::now());
+        self.parser.advance(&mut self.terminal, string);
+        self.write_character_changes();
+    }
+
+    fn write_character_changes(&mut self) {
+        self.upload_characters();
+
+        if !self.update_cursor() {
+            self.render_base_texture();
         }
+    }
 
-        self.characters
-            .write_changes(&self.queue, &self.character_buffer);
-        self.render_base_texture();
+    // Rasterises the glyphs of the changed cells, then uploads them
+    fn upload_characters(&mut self) {
+        let Self {
+            device,
+            queue,
+            glyph_atlas,
+            terminal,
+            ..
+        } = self;
+        let characters = terminal.characters_mut();
+        let mut all = characters.rows_changed();
+
+        loop {
+            glyph_atlas.begin_frame();
+            characters.update_glyphs(all, |code, cluster| {
+                glyph_atlas.glyph(device, queue, code, cluster)
+            });
+
+            // cells in view may use the slots of evicted glyphs
+            let evicted = glyph_atlas.take_evicted();
+            if all || !evicted {
+                break;
+            }
+            all = true;
+        }
+
+        terminal.write_changes(queue, &self.character_buffer, &self.row_buffer);
     }
 
-    fn update_shader_param(&self) {
+    pub fn scroll_view(&mut self, lines: i32) {
+        if self.terminal.characters_mut().scroll_view(lines) {
+            self.write_character_changes();
+        }
+    }
+
+    pub fn scroll_page(&mut self, up: bool) {
+        let page = self.terminal.characters().bounds.1.saturating_sub(1) as i32;
+        self.scroll_view(if up { page } else { -page });
+    }
+
+    pub fn scroll_view_to_bottom(&mut self) {
+        if self.terminal.characters_mut().scroll_view_to_bottom() {
+            self.write_character_changes();
+        }
+    }
+
+    pub fn take_bell(&mut self) -> bool {
+        let bell = self.terminal.take_bell();
+        if bell {
+            self.bell_at = Some(std::time::Instant::now());
+        }
+
+        bell
+    }
+
+    // Uploads the parameters of the effect for a frame at `time`
+    fn update_shader_param(&mut self, time: f32) {
+        let time_since = |instant: Option<std::time::Instant>| {
+            instant.map_or(NEVER, |instant| instant.elapsed().as_secs_f32())
+        };
+        let (columns, rows) = self.terminal.characters().bounds;
+
+        let cell_size = [
+            self.cell_size.0 as f32 / self.scale_factor,
+            self.cell_size.1 as f32 / self.scale_factor,
+        ];
+        let mouse_position = [self.mouse_position.x as f32, self.mouse_position.y as f32];
+
+        let param = &mut self.shader_param;
+        param.delta_time = time - param.time;
+        param.time = time;
+        param.cell_size = cell_size;
+        param.grid_size = [columns, rows];
+        param.cursor_position = self.cursor.position;
+        param.mouse_position = mouse_position;
+        param.cursor_shape = self.cursor.shape;
+        param.cursor_visible = (self.cursor.flags & Cursor::VISIBLE != 0) as u32;
+        param.focused = self.focused as u32;
+        param.frame = param.frame.wrapping_add(1);
+        param.time_since_output = time_since(self.output_at);
+        param.time_since_bell = time_since(self.bell_at);
+        param.scale_factor = self.scale_factor;
+
+        self.write_shader_param();
+    }
+
+    fn write_shader_param(&self) {
         self.queue.write_buffer(
             &self.shader_param_buffer,
             0,
@@ -546,6 +870,54 @@ impl State {
         );
     }
 
+    /// Switches to the effect after the current one, in the order of effect::names.
+    pub fn next_effect(&mut self) {
+        self.effect_watcher = EffectWatcher::new(&effect::next(self.effect_watcher.name()));
+        self.building_effect = None;
+    }
+
+    // Builds the pipelines of a newly loaded effect in the background, as compiling its shaders
+    // would stall the event loop
+    fn build_effect(&mut self, effect: Result<Effect>) {
+        let effect = match effect {
+            Ok(effect) => effect,
+            Err(error) => return self.set_effect(Err(error)),
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let device = self.device.clone();
+        let layouts = [
+            self.texture_bind_group_layout.clone(),
+            self.shader_param_bind_group_layout.clone(),
+        ];
+        let format = self.config.format;
+        std::thread::spawn(move || {
+            let effect_chain =
+                EffectChain::new(&device, &effect, [&layouts[0], &layouts[1]], format);
+            // the receiver is gone if another effect was loaded meanwhile
+            let _ = sender.send(effect_chain.map(|effect_chain| (effect.name, effect_chain)));
+        });
+
+        self.building_effect = Some(receiver);
+    }
+
+    // Replaces the effect with a newly built one, or keeps it and shows why the new one failed
+    fn set_effect(&mut self, effect_chain: Result<(String, EffectChain)>) {
+        match effect_chain {
+            Ok((name, mut effect_chain)) => {
+                tracing::info!("effect {name}");
+                effect_chain.resize(&self.device, self.size);
+                self.effect_chain = effect_chain;
+                self.overlay.set_text(&self.device, &self.queue, None);
+            }
+            Err(error) => {
+                tracing::error!("{error:#}");
+                self.overlay
+                    .set_text(&self.device, &self.queue, Some(&format!("{error:#}")));
+            }
+        }
+    }
+
     fn create_base_texture(
         device: &Device,
         layout: &BindGroupLayout,
@@ -0,0 +1,466 @@
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    character::Character,
+    character_buffer::CharacterBuffer,
+    cursor::{Cursor, CursorShape},
+    keyboard::KeyboardMode,
+    palette::{rgb, Color, Palette},
+    parser::{Params, Perform},
+};
+
+// Attributes applied to the characters being printed
+#[derive(Debug, Clone, Copy)]
+struct Pen {
+    fg: Color,
+    bg: Color,
+    underline_color: Color,
+    flags: u32,
+}
+
+impl Pen {
+    fn set_underline(&mut self, style: u32) {
+        self.flags = (self.flags & !Character::UNDERLINE_MASK)
+            | ((style << Character::UNDERLINE_SHIFT) & Character::UNDERLINE_MASK);
+    }
+}
+
+impl Default for Pen {
+    fn default() -> Self {
+        Self {
+            fg: Color::Default,
+            bg: Color::Default,
+            underline_color: Color::Default,
+            flags: 0,
+        }
+    }
+}
+
+// State saved by DECSC and restored by DECRC
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    position: (u32, u32),
+    pen: Pen,
+    origin_mode: bool,
+}
+
+pub struct Terminal {
+    characters: CharacterBuffer,
+    palette: Palette,
+    pen: Pen,
+    // one for the primary and one for the alternate screen
+    saved_cursors: [Option<SavedCursor>; 2],
+    // character repeated by REP
+    last_printed: Option<char>,
+    bell: bool,
+    blink_used: bool,
+
+    cursor_shape: CursorShape,
+    cursor_blinking: bool,
+    cursor_visible: bool,
+
+    keyboard_mode: KeyboardMode,
+}
+
+impl Terminal {
+    pub fn new(bounds: (u32, u32), scrollback_size: usize, palette: Palette) -> Self {
+        let blank = Character::new(Color::Default, Color::Default, 0);
+
+        Self {
+            characters: CharacterBuffer::new(bounds, scrollback_size, blank),
+            palette,
+            pen: Pen::default(),
+            saved_cursors: [None; 2],
+            last_printed: None,
+            bell: false,
+            blink_used: false,
+
+            cursor_shape: CursorShape::Block,
+            cursor_blinking: true,
+            cursor_visible: true,
+
+            keyboard_mode: KeyboardMode::default(),
+        }
+    }
+
+    pub fn keyboard_mode(&self) -> KeyboardMode {
+        self.keyboard_mode
+    }
+
+    pub fn cursor(&self, focused: bool) -> Cursor {
+        let position = self.characters.cursor_in_view();
+        let mut flags = 0;
+
+        if self.cursor_visible && position.is_some() {
+            flags |= Cursor::VISIBLE;
+        }
+        if self.cursor_blinking {
+            flags |= Cursor::BLINKING;
+        }
+        if focused {
+            flags |= Cursor::FOCUSED;
+        }
+
+        Cursor::new(position.unwrap_or_default(), self.cursor_shape, flags)
+    }
+
+    /// Returns whether blinking text was ever printed, so the view needs to be redrawn as it blinks.
+    pub fn blink_used(&self) -> bool {
+        self.blink_used
+    }
+
+    /// Returns whether a BEL was received since the last call.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    /// Replaces the palette, recolouring the characters drawn in its colours or the default ones.
+    pub fn set_palette(&mut self, palette: Palette) {
+        if palette != self.palette {
+            self.palette = palette;
+            self.characters.mark_all_changed();
+        }
+    }
+
+    pub fn characters(&self) -> &CharacterBuffer {
+        &self.characters
+    }
+
+    pub fn characters_mut(&mut self) -> &mut CharacterBuffer {
+        &mut self.characters
+    }
+
+    /// Uploads the changed characters, see CharacterBuffer::write_changes.
+    pub fn write_changes(
+        &mut self,
+        queue: &wgpu::Queue,
+        character_buffer: &wgpu::Buffer,
+        row_buffer: &wgpu::Buffer,
+    ) {
+        self.characters
+            .write_changes(queue, &self.palette, character_buffer, row_buffer);
+    }
+
+    // DECSC
+    fn save_cursor(&mut self) {
+        self.saved_cursors[self.characters.is_alternate() as usize] = Some(SavedCursor {
+            position: self.characters.cursor_position,
+            pen: self.pen,
+            origin_mode: self.characters.origin_mode(),
+        });
+    }
+
+    // DECRC
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursors[self.characters.is_alternate() as usize];
+        let SavedCursor {
+            position,
+            pen,
+            origin_mode,
+        } = saved.unwrap_or(SavedCursor {
+            position: (0, 0),
+            pen: Pen::default(),
+            origin_mode: false,
+        });
+
+        self.characters.set_origin_mode(origin_mode);
+        self.characters.set_cursor_position(position.0, position.1);
+        self.pen = pen;
+        self.update_blank();
+    }
+
+    // Erased cells take the current background colour
+    fn update_blank(&mut self) {
+        self.characters
+            .set_blank(Character::new(self.pen.bg, self.pen.fg, 0));
+    }
+
+    // DECSET and DECRST
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            // DECCKM
+            1 => self.keyboard_mode.application_cursor = enabled,
+            // DECOM
+            6 => self.characters.set_origin_mode(enabled),
+            // DECAWM
+            7 => self.characters.set_auto_wrap(enabled),
+            // DECTCEM
+            25 => self.cursor_visible = enabled,
+            47 | 1047 => self.characters.set_alternate(enabled),
+            1049 => {
+                if enabled {
+                    self.save_cursor();
+                    self.characters.set_alternate(true);
+                } else {
+                    self.characters.set_alternate(false);
+                    self.restore_cursor();
+                }
+            }
+            _ => tracing::trace!("unhandled private mode {mode} {enabled}"),
+        }
+    }
+
+    // DECSCUSR
+    fn set_cursor_style(&mut self, style: u16) {
+        (self.cursor_shape, self.cursor_blinking) = match style {
+            0 | 1 => (CursorShape::Block, true),
+            2 => (CursorShape::Block, false),
+            3 => (CursorShape::Underline, true),
+            4 => (CursorShape::Underline, false),
+            5 => (CursorShape::Bar, true),
+            6 => (CursorShape::Bar, false),
+            _ => return,
+        };
+    }
+
+    // SGR
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        let groups: Vec<&[u16]> = params.iter().collect();
+
+        if groups.is_empty() {
+            self.pen = Pen::default();
+            self.update_blank();
+            return;
+        }
+
+        let mut index = 0;
+
+        while index < groups.len() {
+            match groups[index][0] {
+                0 => self.pen = Pen::default(),
+                1 => self.pen.flags |= Character::BOLD,
+                2 => self.pen.flags |= Character::FAINT,
+                3 => self.pen.flags |= Character::ITALIC,
+                4 => self.pen.set_underline(match groups[index] {
+                    [_, style, ..] => *style as u32,
+                    _ => Character::UNDERLINE_SINGLE,
+                }),
+                5 | 6 => self.pen.flags |= Character::BLINK,
+                7 => self.pen.flags |= Character::INVERSE,
+                8 => self.pen.flags |= Character::HIDDEN,
+                9 => self.pen.flags |= Character::STRIKETHROUGH,
+                21 => self.pen.set_underline(Character::UNDERLINE_DOUBLE),
+                22 => self.pen.flags &= !(Character::BOLD | Character::FAINT),
+                23 => self.pen.flags &= !Character::ITALIC,
+                24 => self.pen.set_underline(Character::UNDERLINE_NONE),
+                25 => self.pen.flags &= !Character::BLINK,
+                27 => self.pen.flags &= !Character::INVERSE,
+                28 => self.pen.flags &= !Character::HIDDEN,
+                29 => self.pen.flags &= !Character::STRIKETHROUGH,
+                n @ 30..=37 => self.pen.fg = Color::Indexed(n as u8 - 30),
+                38 => {
+                    let (color, used) = Self::extended_color(&groups[index..]);
+                    self.pen.fg = color.unwrap_or(self.pen.fg);
+                    index += used;
+                }
+                39 => self.pen.fg = Color::Default,
+                n @ 40..=47 => self.pen.bg = Color::Indexed(n as u8 - 40),
+                48 => {
+                    let (color, used) = Self::extended_color(&groups[index..]);
+                    self.pen.bg = color.unwrap_or(self.pen.bg);
+                    index += used;
+                }
+                49 => self.pen.bg = Color::Default,
+                53 => self.pen.flags |= Character::OVERLINE,
+                55 => self.pen.flags &= !Character::OVERLINE,
+                58 => {
+                    let (color, used) = Self::extended_color(&groups[index..]);
+                    self.pen.underline_color = color.unwrap_or(self.pen.underline_color);
+                    index += used;
+                }
+                59 => self.pen.underline_color = Color::Default,
+                n @ 90..=97 => self.pen.fg = Color::Indexed(n as u8 - 90 + 8),
+                n @ 100..=107 => self.pen.bg = Color::Indexed(n as u8 - 100 + 8),
+                n => tracing::trace!("unhandled SGR {n}"),
+            }
+
+            index += 1;
+        }
+
+        self.update_blank();
+    }
+
+    // Parses the colour of a 38, 48 or 58 SGR, either in its `38;5;n` and `38;2;r;g;b` form or in its
+    // `38:5:n` and `38:2:[colorspace]:r:g:b` form. Also returns how many extra parameters were used.
+    fn extended_color(groups: &[&[u16]]) -> (Option<Color>, usize) {
+        let indexed = |index: u16| u8::try_from(index).ok().map(Color::Indexed);
+        let direct = |channels: &[u16]| match channels {
+            &[r, g, b] => Some(Color::Rgb(rgb(r as u8, g as u8, b as u8))),
+            _ => None,
+        };
+
+        match groups[0] {
+            [_, 5, index, ..] => (indexed(*index), 0),
+            [_, 2, _, r, g, b, ..] => (direct(&[*r, *g, *b]), 0),
+            [_, 2, r, g, b] => (direct(&[*r, *g, *b]), 0),
+            [_, ..] if groups[0].len() > 1 => (None, 0),
+            _ => match groups.get(1).map(|group| group[0]) {
+                Some(5) => (groups.get(2).and_then(|group| indexed(group[0])), 2),
+                Some(2) => {
+                    let channels: Vec<u16> = groups
+                        .iter()
+                        .skip(2)
+                        .take(3)
+                        .map(|group| group[0])
+                        .collect();
+                    (direct(&channels), 4)
+                }
+                _ => (None, 0),
+            },
+        }
+    }
+}
+
+impl Perform for Terminal {
+    fn print(&mut self, c: char) {
+        if self.characters.combine(c) {
+            return;
+        }
+
+        let width = match c.width() {
+            Some(width @ 1..) => width,
+            _ => return,
+        };
+
+        let underline_color = match self.pen.underline_color {
+            Color::Default => None,
+            color => Some(color),
+        };
+
+        self.last_printed = Some(c);
+        self.blink_used |= self.pen.flags & Character::BLINK != 0;
+        self.characters.push_char(
+            Character::new(self.pen.bg, self.pen.fg, c as u32)
+                .with_attributes(self.pen.flags, underline_color),
+            width == 2,
+        );
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x07 => self.bell = true,
+            0x08 => self.characters.backspace(),
+            0x09 => self.characters.tab(),
+            0x0a..=0x0c => self.characters.line_feed(),
+            0x0d => self.characters.carriage_return(),
+            // IND, NEL, HTS, RI
+            0x84 => self.characters.line_feed(),
+            0x85 => {
+                self.characters.carriage_return();
+                self.characters.line_feed();
+            }
+            0x88 => self.characters.set_tab_stop(),
+            0x8d => self.characters.reverse_line_feed(),
+            _ => tracing::trace!("unhandled control character {byte:#04x}"),
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+
+        let count = params.get_or(0, 1) as u32;
+        let characters = &mut self.characters;
+
+        match (intermediates, action) {
+            // CUU, CUD, CUF, CUB
+            ([], 'A') => characters.move_cursor(0, -(count as i32)),
+            ([], 'B') => characters.move_cursor(0, count as i32),
+            ([], 'C') => characters.move_cursor(count as i32, 0),
+            ([], 'D') => characters.move_cursor(-(count as i32), 0),
+            // CNL, CPL
+            ([], 'E') => {
+                characters.move_cursor(0, count as i32);
+                characters.carriage_return();
+            }
+            ([], 'F') => {
+                characters.move_cursor(0, -(count as i32));
+                characters.carriage_return();
+            }
+            // CHA, VPA
+            ([], 'G') | ([], '`') => characters.goto_column(count - 1),
+            ([], 'd') => characters.goto_line(count - 1),
+            // CUP, HVP
+            ([], 'H') | ([], 'f') => characters.goto(
+                params.get_or(1, 1) as u32 - 1,
+                params.get_or(0, 1) as u32 - 1,
+            ),
+            // ED, EL, ECH
+            ([], 'J') => characters.erase_in_display(params.get_or(0, 0)),
+            ([], 'K') => characters.erase_in_line(params.get_or(0, 0)),
+            ([], 'X') => characters.erase_characters(count),
+            // ICH, DCH, IL, DL
+            ([], '@') => characters.insert_blanks(count),
+            ([], 'P') => characters.delete_characters(count),
+            ([], 'L') => characters.insert_lines(count),
+            ([], 'M') => characters.delete_lines(count),
+            // SU, SD
+            ([], 'S') => characters.scroll_up(count),
+            ([], 'T') => characters.scroll_down(count),
+            // REP
+            ([], 'b') => {
+                if let Some(c) = self.last_printed {
+                    let area = characters.bounds.0 * characters.bounds.1;
+                    for _ in 0..count.min(area) {
+                        self.print(c);
+                    }
+                }
+            }
+            // TBC
+            ([], 'g') => match params.get_or(0, 0) {
+                0 => characters.clear_tab_stop(),
+                3 => characters.clear_all_tab_stops(),
+                _ => {}
+            },
+            ([], 'm') => self.select_graphic_rendition(params),
+            // SCOSC, SCORC
+            ([], 's') => self.save_cursor(),
+            ([], 'u') => self.restore_cursor(),
+            ([b'?'], 'h') | ([b'?'], 'l') => {
+                for mode in params.iter() {
+                    self.set_private_mode(mode[0], action == 'h');
+                }
+            }
+            ([b' '], 'q') => self.set_cursor_style(params.get_or(0, 0)),
+            // DECSTBM
+            ([], 'r') => {
+                let bottom = params.get_or(1, characters.bounds.1 as u16) as u32;
+                characters.set_scroll_region(params.get_or(0, 1) as u32 - 1, bottom);
+            }
+            _ => tracing::trace!("unhandled CSI {params:?} {intermediates:?} {action}"),
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore {
+            return;
+        }
+
+        match (intermediates, byte) {
+            // IND, NEL, HTS, RI
+            ([], b'D') => self.execute(0x84),
+            ([], b'E') => self.execute(0x85),
+            ([], b'H') => self.execute(0x88),
+            ([], b'M') => self.execute(0x8d),
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            // DECKPAM, DECKPNM
+            ([], b'=') => self.keyboard_mode.application_keypad = true,
+            ([], b'>') => self.keyboard_mode.application_keypad = false,
+            _ => tracing::trace!("unhandled ESC {intermediates:?} {}", byte as char),
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&str], _bell_terminated: bool) {
+        tracing::trace!("unhandled OSC {params:?}");
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        tracing::trace!("unhandled DCS {params:?} {intermediates:?} {action}");
+    }
+
+    fn put(&mut self, _c: char) {}
+
+    fn unhook(&mut self) {}
+}
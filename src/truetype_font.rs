@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use swash::{
+    scale::{image::Content, Render, ScaleContext, Source, StrikeWith},
+    shape::ShapeContext,
+    zeno::{Format, Vector},
+    CacheKey, FontRef,
+};
+
+use crate::font::{GlyphSource, Tile};
+
+/// TrueType or OpenType font, rasterised with swash.
+pub struct TrueTypeFont {
+    data: FontData,
+    // pixels per em
+    size: f32,
+
+    shape_context: ShapeContext,
+    scale_context: ScaleContext,
+}
+
+// kept apart from the contexts, so drawing can borrow the font while using them
+struct FontData {
+    data: Vec<u8>,
+    offset: u32,
+    key: CacheKey,
+}
+
+impl FontData {
+    fn font(&self) -> FontRef<'_> {
+        FontRef {
+            data: &self.data,
+            offset: self.offset,
+            key: self.key,
+        }
+    }
+}
+
+impl TrueTypeFont {
+    pub fn new(data: Vec<u8>, size: f32) -> Result<Self> {
+        let font =
+            FontRef::from_index(&data, 0).ok_or(anyhow!("not a TrueType or OpenType font"))?;
+        let (offset, key) = (font.offset, font.key);
+
+        Ok(Self {
+            data: FontData { data, offset, key },
+            size,
+
+            shape_context: ShapeContext::new(),
+            scale_context: ScaleContext::new(),
+        })
+    }
+}
+
+impl GlyphSource for TrueTypeFont {
+    fn cell_size(&self) -> (u32, u32) {
+        let font = self.data.font();
+        let metrics = font.metrics(&[]).scale(self.size);
+        let advance = font
+            .glyph_metrics(&[])
+            .scale(self.size)
+            .advance_width(font.charmap().map('M'));
+
+        (
+            advance.round().max(1.0) as u32,
+            (metrics.ascent + metrics.descent + metrics.leading)
+                .ceil()
+                .max(1.0) as u32,
+        )
+    }
+
+    fn draw(&mut self, cluster: &str, tile: &mut Tile) -> bool {
+        let font = self.data.font();
+        let size = self.size;
+
+        // half of the line gap goes above the glyphs
+        let metrics = font.metrics(&[]).scale(size);
+        let baseline = (metrics.leading / 2.0 + metrics.ascent).round();
+
+        let mut glyphs = Vec::new();
+        let mut shaper = self.shape_context.builder(font).size(size).build();
+        shaper.add_str(cluster);
+        shaper.shape_with(|cluster| glyphs.extend_from_slice(cluster.glyphs));
+
+        if glyphs.is_empty() || glyphs.iter().any(|glyph| glyph.id == 0) {
+            return false;
+        }
+
+        let mut scaler = self
+            .scale_context
+            .builder(font)
+            .size(size)
+            .hint(true)
+            .build();
+        let mut pen = 0.0;
+
+        for glyph in glyphs {
+            let x = pen + glyph.x;
+            // colour glyphs first, for emoji fonts
+            let image = Render::new(&[
+                Source::ColorOutline(0),
+                Source::ColorBitmap(StrikeWith::BestFit),
+                Source::Outline,
+            ])
+            .format(Format::Alpha)
+            .offset(Vector::new(x.fract(), 0.0))
+            .render(&mut scaler, glyph.id);
+
+            if let Some(image) = image {
+                let (x, y) = (
+                    x.floor() as i32 + image.placement.left,
+                    baseline as i32 - glyph.y.round() as i32 - image.placement.top,
+                );
+                let (width, height) = (image.placement.width, image.placement.height);
+
+                match image.content {
+                    Content::Color => tile.blit_color(x, y, width, height, &image.data),
+                    _ => tile.blit(x, y, width, height, &image.data),
+                }
+            }
+
+            pen += glyph.advance;
+        }
+
+        true
+    }
+}
@@ -23,14 +23,23 @@ impl TryReader {
         thread::spawn(move || {
             let keep_alive = Arc::downgrade(&keep_alive_clone);
             let mut buf = vec![0; 65536];
+            let mut pending = Vec::new();
 
             while keep_alive.strong_count() > 0 {
                 let amount = reader.read(buf.as_mut_slice()).unwrap_or(0);
 
                 if amount > 0 {
-                    tx.send(String::from_utf8_lossy(&buf[..amount]).into_owned())
+                    pending.extend_from_slice(&buf[..amount]);
+
+                    // keep a UTF-8 sequence split across two reads for the next one
+                    let complete = match std::str::from_utf8(&pending) {
+                        Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                        _ => pending.len(),
+                    };
+
+                    tx.send(String::from_utf8_lossy(&pending[..complete]).into_owned())
                         .expect("could not send string from try_read thread to main thread");
-                    buf.clear();
+                    pending.drain(..complete);
                 }
             }
         });